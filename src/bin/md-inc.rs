@@ -1,5 +1,4 @@
 use anyhow::Result;
-use md_inc;
 use structopt::StructOpt;
 
 fn main() -> Result<()> {
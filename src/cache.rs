@@ -0,0 +1,67 @@
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+
+///
+/// Finds the files matching a list of glob patterns, relative to `base_dir`.
+/// Files are sorted, so the cache key doesn't depend on the order of the file system.
+///
+fn input_files(base_dir: &Path, patterns: &[&str]) -> Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    for pattern in patterns {
+        let full = crate::parse::glob_pattern(base_dir, pattern)?;
+        let matches = glob::glob(&full)
+            .with_context(|| format!("Invalid input file pattern: '{}'", pattern))?
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        if matches.is_empty() {
+            return Err(anyhow::anyhow!("No input files match '{}'", pattern));
+        }
+        files.extend(matches.into_iter().filter(|x| x.is_file()));
+    }
+    files.sort();
+    files.dedup();
+    Ok(files)
+}
+
+///
+/// Creates a cache key from the text of a source and the contents of its input files.
+///
+/// # Parameters
+/// * `source` The source and its arguments, as written in the command block
+/// * `base_dir` The directory input files are relative to
+/// * `inputs` Glob patterns of files the output depends on
+///
+pub(crate) fn key(source: &[&str], base_dir: &Path, inputs: &[&str]) -> Result<String> {
+    let mut hasher = Sha256::new();
+    for part in source {
+        hasher.update(part.as_bytes());
+        hasher.update([0]);
+    }
+    for file in input_files(base_dir, inputs)? {
+        let contents =
+            std::fs::read(&file).with_context(|| format!("Could not read input {:?}", file))?;
+        // Paths are hashed relative to `base_dir`, so moving the project keeps the cache
+        let name = file.strip_prefix(base_dir).unwrap_or(&file);
+        hasher.update(name.to_string_lossy().as_bytes());
+        hasher.update([0]);
+        hasher.update(Sha256::digest(&contents));
+    }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|x| format!("{:02x}", x))
+        .collect())
+}
+
+/// Reads the cached output for `key`, if there is one
+pub(crate) fn get(dir: &Path, key: &str) -> Option<String> {
+    std::fs::read_to_string(dir.join(key)).ok()
+}
+
+/// Saves the output for `key`
+pub(crate) fn set(dir: &Path, key: &str, text: &str) -> Result<()> {
+    std::fs::create_dir_all(dir)
+        .with_context(|| format!("Could not create cache directory {:?}", dir))?;
+    std::fs::write(dir.join(key), text)
+        .with_context(|| format!("Could not write to cache directory {:?}", dir))
+}
@@ -1,13 +1,17 @@
+use crate::exec::{self, ExecConfig};
 use crate::parse::CommandTags;
+use crate::render::Renderer;
 use crate::ParserConfig;
 use anyhow::Context;
 use anyhow::Result;
+use std::collections::HashMap;
 use std::fs::read_to_string;
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-pub static DEFAULT_TAG_BEGIN: &'static str = "<!--{";
-pub static DEFAULT_TAG_END: &'static str = "}-->";
-pub static DEFAULT_END_COMMAND: &'static str = "end";
+pub static DEFAULT_TAG_BEGIN: &str = "<!--{";
+pub static DEFAULT_TAG_END: &str = "}-->";
+pub static DEFAULT_END_COMMAND: &str = "end";
 
 #[derive(Clone, Debug, Deserialize, PartialEq)]
 #[serde(default)]
@@ -35,6 +39,31 @@ pub struct Config {
 
     /// Relative path of output directory
     pub out_dir: Option<String>,
+
+    /// Languages used by the `code` command, keyed by file extension.
+    /// These are added to (or replace) the default languages.
+    pub languages: HashMap<String, String>,
+
+    /// The markdown renderer that output is written for
+    pub renderer: Renderer,
+
+    /// If true, code blocks are given a title of the included file path
+    pub code_title: bool,
+
+    /// If true, `@exec` can run commands
+    pub allow_exec: bool,
+
+    /// Relative path of the working directory for `@exec` (defaults to `base_dir`)
+    pub exec_dir: Option<String>,
+
+    /// The number of seconds a command can run before it is stopped
+    pub exec_timeout: u64,
+
+    /// The environment variables passed to commands
+    pub exec_env: Vec<String>,
+
+    /// Relative path of the directory to cache the output of `@exec` and `@session` in
+    pub cache_dir: Option<String>,
 }
 
 impl Default for Config {
@@ -48,10 +77,55 @@ impl Default for Config {
             next_dirs: vec![],
             depend_dirs: vec![],
             out_dir: None,
+            languages: HashMap::new(),
+            renderer: Renderer::default(),
+            code_title: false,
+            allow_exec: false,
+            exec_dir: None,
+            exec_timeout: exec::DEFAULT_TIMEOUT,
+            exec_env: exec::default_env(),
+            cache_dir: None,
         }
     }
 }
 
+///
+/// The default languages used by the `code` command, keyed by file extension
+///
+pub fn default_languages() -> HashMap<String, String> {
+    [
+        ("c", "c"),
+        ("cpp", "cpp"),
+        ("cs", "csharp"),
+        ("css", "css"),
+        ("go", "go"),
+        ("h", "c"),
+        ("hpp", "cpp"),
+        ("html", "html"),
+        ("ipynb", "python"),
+        ("java", "java"),
+        ("js", "javascript"),
+        ("json", "json"),
+        ("kt", "kotlin"),
+        ("md", "markdown"),
+        ("php", "php"),
+        ("py", "python"),
+        ("rb", "ruby"),
+        ("rs", "rust"),
+        ("sh", "bash"),
+        ("sql", "sql"),
+        ("swift", "swift"),
+        ("toml", "toml"),
+        ("ts", "typescript"),
+        ("xml", "xml"),
+        ("yaml", "yaml"),
+        ("yml", "yaml"),
+    ]
+    .iter()
+    .map(|(ext, lang)| (ext.to_string(), lang.to_string()))
+    .collect()
+}
+
 #[derive(Clone, Debug)]
 pub struct ConfigAndPath {
     pub config: Config,
@@ -68,11 +142,24 @@ impl ConfigAndPath {
     }
     pub(crate) fn into_parser(self) -> Result<(ParserConfig, Vec<PathBuf>)> {
         let parent = self.parent_path()?;
+        let mut languages = default_languages();
+        languages.extend(self.config.languages);
         Ok((
             ParserConfig {
                 tags: CommandTags::new(self.config.open_tag, self.config.close_tag),
                 end_command: self.config.end_command,
                 base_dir: parent.join(self.config.base_dir),
+                languages,
+                renderer: self.config.renderer,
+                code_title: self.config.code_title,
+                exec: ExecConfig {
+                    allow: self.config.allow_exec,
+                    dir: self.config.exec_dir.map(|x| parent.join(x)),
+                    timeout: Duration::from_secs(self.config.exec_timeout),
+                    env: self.config.exec_env,
+                },
+                cache_dir: self.config.cache_dir.map(|x| parent.join(x)),
+                ..Default::default()
             },
             self.config.files.iter().map(|x| parent.join(x)).collect(),
         ))
@@ -82,9 +169,7 @@ impl Config {
     pub(crate) fn try_from_path<P: AsRef<Path>>(dir: P) -> Result<Self> {
         let file =
             read_to_string(dir.as_ref()).with_context(|| format!("Reading {:?}", dir.as_ref()))?;
-        Ok(toml::from_str::<Config>(&file)
-            .context("Error in toml config file")?
-            .into())
+        toml::from_str::<Config>(&file).context("Error in toml config file")
     }
 
     /// Returns None if no file exists
@@ -0,0 +1,51 @@
+use anyhow::{Context, Result};
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, SecondsFormat, Utc};
+
+///
+/// The current time as a Unix timestamp.
+/// If `SOURCE_DATE_EPOCH` is set, it is used instead, so builds are reproducible.
+///
+pub(crate) fn now() -> Result<i64> {
+    if let Ok(epoch) = std::env::var("SOURCE_DATE_EPOCH") {
+        return epoch
+            .trim()
+            .parse()
+            .with_context(|| format!("Invalid SOURCE_DATE_EPOCH: '{}'", epoch));
+    }
+    Ok(std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)?
+        .as_secs() as i64)
+}
+
+/// Converts a Unix timestamp to a date
+fn from_timestamp(timestamp: i64) -> Result<DateTime<Utc>> {
+    DateTime::from_timestamp(timestamp, 0)
+        .with_context(|| format!("Timestamp is out of range: {}", timestamp))
+}
+
+///
+/// Writes a Unix timestamp as an RFC 3339 date in UTC, e.g. `2020-06-01T12:30:00Z`
+///
+pub(crate) fn to_rfc3339(timestamp: i64) -> Result<String> {
+    Ok(from_timestamp(timestamp)?.to_rfc3339_opts(SecondsFormat::Secs, true))
+}
+
+///
+/// Formats a date given as RFC 3339 or a Unix timestamp, using `strftime` syntax (e.g. `%Y-%m-%d`).
+/// Dates are shown in UTC.
+///
+pub(crate) fn format(date: &str, format: &str) -> Result<String> {
+    let date = date.trim();
+    let date = match date.parse::<i64>() {
+        Ok(timestamp) => from_timestamp(timestamp)?,
+        Err(_) => DateTime::parse_from_rfc3339(date)
+            .with_context(|| format!("Invalid date: '{}'", date))?
+            .with_timezone(&Utc),
+    };
+    let items = StrftimeItems::new(format).collect::<Vec<_>>();
+    if items.contains(&Item::Error) {
+        return Err(anyhow::anyhow!("Invalid date format: '{}'", format));
+    }
+    Ok(date.format_with_items(items.into_iter()).to_string())
+}
@@ -0,0 +1,83 @@
+use anyhow::Result;
+use similar::{ChangeTag, TextDiff};
+
+///
+/// Lines of a file to compare
+///
+pub(crate) struct DiffInput<'a> {
+    /// The name shown in the diff header
+    pub name: &'a str,
+    pub lines: Vec<&'a str>,
+    /// The line number of the first line in the file
+    pub first_line: usize,
+}
+
+impl<'a> DiffInput<'a> {
+    ///
+    /// Compares only the lines at `indices` (0-based).
+    /// Returns an error if the lines aren't consecutive and ascending, as a hunk can't show them.
+    ///
+    pub fn select(name: &'a str, lines: &[&'a str], indices: &[usize]) -> Result<Self> {
+        if indices.windows(2).any(|x| x[1] != x[0] + 1) {
+            return Err(anyhow::anyhow!(
+                "The lines compared in '{}' must be a single ascending range, e.g. lines=3-8",
+                name
+            ));
+        }
+        Ok(Self {
+            name,
+            lines: indices.iter().map(|i| lines[*i]).collect(),
+            first_line: indices.first().map_or(1, |x| x + 1),
+        })
+    }
+}
+
+/// Formats a hunk range as `start,count`, where `count` is omitted if it is 1
+fn hunk_range(start: usize, len: usize, first_line: usize) -> String {
+    // An empty range refers to the line before it
+    let start = match len {
+        0 => start + first_line - 1,
+        _ => start + first_line,
+    };
+    match len {
+        1 => start.to_string(),
+        _ => format!("{},{}", start, len),
+    }
+}
+
+///
+/// Creates a unified diff from `old` to `new`, with `context` unchanged lines around each change.
+/// Returns an empty string if the inputs are the same.
+///
+pub(crate) fn unified(old: &DiffInput, new: &DiffInput, context: usize) -> String {
+    let diff = TextDiff::from_slices(&old.lines, &new.lines);
+    let mut output = vec![];
+    for group in diff.grouped_ops(context) {
+        let (first, last) = match (group.first(), group.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => continue,
+        };
+        if output.is_empty() {
+            output.push(format!("--- {}", old.name));
+            output.push(format!("+++ {}", new.name));
+        }
+        let old_range = first.old_range().start..last.old_range().end;
+        let new_range = first.new_range().start..last.new_range().end;
+        output.push(format!(
+            "@@ -{} +{} @@",
+            hunk_range(old_range.start, old_range.len(), old.first_line),
+            hunk_range(new_range.start, new_range.len(), new.first_line),
+        ));
+        for op in &group {
+            for change in diff.iter_changes(op) {
+                let sign = match change.tag() {
+                    ChangeTag::Equal => ' ',
+                    ChangeTag::Delete => '-',
+                    ChangeTag::Insert => '+',
+                };
+                output.push(format!("{}{}", sign, change.value()));
+            }
+        }
+    }
+    output.join("\n")
+}
@@ -0,0 +1,267 @@
+use anyhow::{Context, Result};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+///
+/// Settings for running commands with the `@exec` source
+///
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExecConfig {
+    /// Commands are only run if this is true
+    pub allow: bool,
+    /// The working directory of commands (defaults to the base directory)
+    pub dir: Option<PathBuf>,
+    /// Commands that take longer than this are stopped
+    pub timeout: Duration,
+    /// The environment variables passed to commands. Other variables are removed.
+    pub env: Vec<String>,
+}
+
+/// The default time limit for running a command, in seconds
+pub(crate) static DEFAULT_TIMEOUT: u64 = 60;
+
+/// The environment variables passed to commands by default
+pub(crate) fn default_env() -> Vec<String> {
+    vec!["PATH".to_string(), "HOME".to_string()]
+}
+
+impl ExecConfig {
+    ///
+    /// The settings that change the output of commands, as `name=value` strings for cache keys.
+    /// The working directory is relative to `base_dir`, so moving the project keeps the cache.
+    ///
+    pub(crate) fn key_parts(&self, base_dir: &Path) -> Vec<String> {
+        let dir = self
+            .dir
+            .as_deref()
+            .map(|x| x.strip_prefix(base_dir).unwrap_or(x))
+            .unwrap_or_else(|| Path::new(""));
+        let mut parts = vec![
+            format!("dir={}", dir.to_string_lossy()),
+            format!("timeout={}", self.timeout.as_millis()),
+        ];
+        parts.extend(self.env.iter().map(|key| {
+            let value = std::env::var_os(key).unwrap_or_default();
+            format!("env={}={}", key, value.to_string_lossy())
+        }));
+        parts
+    }
+}
+
+impl Default for ExecConfig {
+    fn default() -> Self {
+        Self {
+            allow: false,
+            dir: None,
+            timeout: Duration::from_secs(DEFAULT_TIMEOUT),
+            env: default_env(),
+        }
+    }
+}
+
+///
+/// How the output of a command is read
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Capture {
+    /// stdout and stderr are read separately
+    Separate,
+    /// stdout and stderr are written to one pipe (like `2>&1`), so the output keeps the order
+    /// it was written in. It is read into `Output::stdout`.
+    Combined,
+}
+
+///
+/// The output of a command that has finished
+///
+pub(crate) struct Output {
+    pub stdout: String,
+    /// Empty if the output was captured with `Capture::Combined`
+    pub stderr: String,
+    /// The exit code, or `None` if the command was stopped by a signal
+    pub code: Option<i32>,
+}
+
+///
+/// Splits a command line into words, like a shell.
+/// Words may be quoted with `"` or `'` to include spaces.
+///
+pub(crate) fn split_words(line: &str) -> Result<Vec<String>> {
+    let mut words = vec![];
+    let mut word: Option<String> = None;
+    let mut quote: Option<char> = None;
+    for x in line.chars() {
+        match (quote, x) {
+            (Some(q), x) if q == x => quote = None,
+            (Some(_), x) => word.get_or_insert_with(String::new).push(x),
+            (None, '"') | (None, '\'') => {
+                quote = Some(x);
+                word.get_or_insert_with(String::new);
+            }
+            (None, x) if x.is_whitespace() => words.extend(word.take()),
+            (None, x) => word.get_or_insert_with(String::new).push(x),
+        }
+    }
+    if quote.is_some() {
+        return Err(anyhow::anyhow!("Unclosed quote in command: {}", line));
+    }
+    words.extend(word.take());
+    Ok(words)
+}
+
+/// Reads a pipe to a string on another thread, so a full pipe can't block the command.
+/// The output is sent when the pipe is closed.
+fn read_pipe<R: Read + Send + 'static>(pipe: Option<R>) -> Receiver<String> {
+    let (sender, receiver) = channel();
+    std::thread::spawn(move || {
+        let mut output = vec![];
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut output);
+        }
+        let _ = sender.send(String::from_utf8_lossy(&output).into_owned());
+    });
+    receiver
+}
+
+///
+/// Runs a command and waits for it to finish.
+///
+/// # Parameters
+/// * `config` The settings for running commands
+/// * `base_dir` The working directory, if `config` doesn't set one
+/// * `words` The program to run, followed by its arguments
+/// * `capture` Whether stdout and stderr are read separately or together
+///
+pub(crate) fn run(
+    config: &ExecConfig,
+    base_dir: &Path,
+    words: &[String],
+    capture: Capture,
+) -> Result<Output> {
+    if !config.allow {
+        return Err(anyhow::anyhow!(
+            "Running commands is disabled. Set `allow_exec = true` in .md-inc.toml to enable it."
+        ));
+    }
+    let (program, args) = words.split_first().context("Missing command to run")?;
+    let dir = config.dir.as_deref().unwrap_or(base_dir);
+    let mut command = Command::new(program);
+    command
+        .args(args)
+        .current_dir(dir)
+        .env_clear()
+        .envs(
+            config
+                .env
+                .iter()
+                .filter_map(|key| std::env::var_os(key).map(|value| (key, value))),
+        )
+        .stdin(Stdio::null());
+    let combined = match capture {
+        Capture::Separate => {
+            command.stdout(Stdio::piped()).stderr(Stdio::piped());
+            None
+        }
+        Capture::Combined => {
+            let (reader, writer) = std::io::pipe()?;
+            command.stdout(writer.try_clone()?).stderr(writer);
+            Some(reader)
+        }
+    };
+    let mut child = command
+        .spawn()
+        .with_context(|| format!("Could not run '{}' in {:?}", words.join(" "), dir))?;
+    // Close the writing end of the combined pipe, so reading stops when the command exits
+    drop(command);
+    let stdout = match combined {
+        Some(reader) => read_pipe(Some(reader)),
+        None => read_pipe(child.stdout.take()),
+    };
+    let stderr = read_pipe(child.stderr.take());
+    let deadline = Instant::now() + config.timeout;
+    let timed_out = || {
+        anyhow::anyhow!(
+            "'{}' did not finish within {} seconds",
+            words.join(" "),
+            config.timeout.as_secs_f32()
+        )
+    };
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if Instant::now() > deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(timed_out());
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    };
+    // Processes started in the background by the command may keep the pipes open,
+    // so reading the output is limited by the same deadline
+    let read = |pipe: Receiver<String>| {
+        pipe.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+            .or_else(|error| match error {
+                RecvTimeoutError::Timeout => Err(timed_out()),
+                RecvTimeoutError::Disconnected => Ok(String::new()),
+            })
+    };
+    Ok(Output {
+        stdout: read(stdout)?,
+        stderr: read(stderr)?,
+        code: status.code(),
+    })
+}
+
+impl Output {
+    ///
+    /// Checks the exit code of a command
+    ///
+    /// # Parameters
+    /// * `words` The command that was run, for the error message
+    /// * `expected` The expected exit code, or `any`
+    ///
+    pub fn check_exit(&self, words: &[String], expected: &str) -> Result<()> {
+        if expected == "any" {
+            return Ok(());
+        }
+        let expected = expected
+            .parse::<i32>()
+            .with_context(|| format!("Invalid 'exit' option: '{}'", expected))?;
+        match self.code == Some(expected) {
+            true => Ok(()),
+            false => Err(anyhow::anyhow!(
+                "'{}' exited with {} (expected {})\n{}",
+                words.join(" "),
+                self.code
+                    .map_or("a signal".to_string(), |x| format!("code {}", x)),
+                expected,
+                match self.stderr.is_empty() {
+                    true => self.stdout.trim_end(),
+                    false => self.stderr.trim_end(),
+                }
+            )),
+        }
+    }
+
+    ///
+    /// Returns `stdout`, `stderr` or `both`.
+    /// Use `Capture::Combined` for `both`, so the output is in the order it was written.
+    ///
+    pub fn text(self, which: &str) -> Result<String> {
+        Ok(match which {
+            "stdout" => self.stdout,
+            "stderr" => self.stderr,
+            "both" => self.stdout + &self.stderr,
+            x => {
+                return Err(anyhow::anyhow!(
+                    "Invalid 'output' option: '{}' (expected stdout, stderr or both)",
+                    x
+                ))
+            }
+        })
+    }
+}
@@ -0,0 +1,106 @@
+use anyhow::{Context, Result};
+use std::path::Path;
+use std::process::Command;
+
+/// The prefix of sources read from a git revision, e.g. `git:v0.3.0:src/lib.rs`
+pub(crate) static GIT_PREFIX: &str = "git:";
+
+///
+/// Runs git in `dir` and returns its output
+///
+fn git(dir: &Path, args: &[&str]) -> Result<std::process::Output> {
+    Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()
+        .map_err(|err| match err.kind() {
+            std::io::ErrorKind::NotFound => {
+                anyhow::anyhow!("Could not run git: it is not installed or not on the PATH")
+            }
+            _ => anyhow::Error::new(err).context("Could not run git"),
+        })
+}
+
+///
+/// Runs git in `dir` and returns its output, or an error if it fails
+///
+fn run(dir: &Path, args: &[&str]) -> Result<String> {
+    let output = git(dir, args)?;
+    if output.status.success() {
+        return Ok(String::from_utf8_lossy(&output.stdout).into_owned());
+    }
+    // Check for a repository after a failure, so a successful command only runs git once
+    if !git(dir, &["rev-parse", "--git-dir"])?.status.success() {
+        return Err(anyhow::anyhow!("{:?} is not in a git repository", dir));
+    }
+    Err(anyhow::anyhow!(
+        "'git {}' failed: {}",
+        args.join(" "),
+        String::from_utf8_lossy(&output.stderr).trim()
+    ))
+}
+
+///
+/// Splits a source of the form `git:revision:path` into the revision and path
+///
+pub(crate) fn parse_source(source: &str) -> Result<(&str, &str)> {
+    let rest = source.strip_prefix(GIT_PREFIX).unwrap_or(source);
+    match rest.split_once(':') {
+        // Don't let a revision be read as an option
+        Some((rev, path)) if !rev.is_empty() && !rev.starts_with('-') && !path.is_empty() => {
+            Ok((rev, path))
+        }
+        _ => Err(anyhow::anyhow!(
+            "Invalid git source: '{}' (expected git:revision:path)",
+            source
+        )),
+    }
+}
+
+///
+/// Reads a file as it was at a revision (a tag, branch or commit) of the local repository.
+///
+/// # Parameters
+/// * `dir` A directory in the repository, which `path` is relative to
+/// * `rev` The revision to read the file from
+/// * `path` The file to read
+///
+pub(crate) fn show(dir: &Path, rev: &str, path: &str) -> Result<String> {
+    run(dir, &["show", &format!("{}:./{}", rev, path)])
+        .with_context(|| format!("Could not read '{}' at revision '{}'", path, rev))
+}
+
+///
+/// The hash of the current commit
+///
+pub(crate) fn commit(dir: &Path, short: bool) -> Result<String> {
+    let args: &[&str] = match short {
+        true => &["rev-parse", "--short", "HEAD"],
+        false => &["rev-parse", "HEAD"],
+    };
+    Ok(run(dir, args)?.trim().to_string())
+}
+
+///
+/// The most recent tag reachable from the current commit
+///
+pub(crate) fn latest_tag(dir: &Path) -> Result<String> {
+    Ok(run(dir, &["describe", "--tags", "--abbrev=0"])?
+        .trim()
+        .to_string())
+}
+
+///
+/// The date of the last commit that changed `path`, as a Unix timestamp
+///
+pub(crate) fn file_date(dir: &Path, path: &str) -> Result<i64> {
+    let output = run(dir, &["log", "-1", "--format=%ct", "--", path])?;
+    let output = output.trim();
+    match output.is_empty() {
+        true => Err(anyhow::anyhow!("'{}' has not been committed", path)),
+        false => output
+            .parse()
+            .with_context(|| format!("Invalid commit date: '{}'", output)),
+    }
+}
@@ -0,0 +1,127 @@
+use anyhow::{Context, Result};
+use serde_json::Value;
+
+/// Selects cells from a notebook
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Selector<'a> {
+    /// The position of the cell in the notebook (1-based index)
+    Index(usize),
+    /// Cells with a tag in `metadata.tags`
+    Tag(&'a str),
+    /// The cell with a matching `id`
+    Id(&'a str),
+}
+
+/// The parts of a cell to include
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Show {
+    Source,
+    Outputs,
+    Both,
+}
+
+impl Show {
+    pub(crate) fn from_arg(arg: &str) -> Result<Self> {
+        Ok(match arg {
+            "source" => Show::Source,
+            "outputs" => Show::Outputs,
+            "both" => Show::Both,
+            x => {
+                return Err(anyhow::anyhow!(
+                    "Invalid 'show' option: '{}' (expected source, outputs or both)",
+                    x
+                ))
+            }
+        })
+    }
+}
+
+/// Joins a notebook string field, which may be a single string or a list of lines
+fn text(value: Option<&Value>) -> String {
+    match value {
+        Some(Value::String(x)) => x.clone(),
+        Some(Value::Array(lines)) => lines.iter().filter_map(|x| x.as_str()).collect(),
+        _ => String::new(),
+    }
+}
+
+/// Collects the text outputs of a code cell (streams, plain text results and errors)
+fn outputs(cell: &Value) -> String {
+    cell.get("outputs")
+        .and_then(|x| x.as_array())
+        .map(|outputs| {
+            outputs
+                .iter()
+                .map(
+                    |output| match output.get("output_type").and_then(|x| x.as_str()) {
+                        Some("stream") => text(output.get("text")),
+                        Some("execute_result") | Some("display_data") => {
+                            text(output.get("data").and_then(|x| x.get("text/plain")))
+                        }
+                        Some("error") => format!(
+                            "{}: {}",
+                            text(output.get("ename")),
+                            text(output.get("evalue"))
+                        ),
+                        _ => String::new(),
+                    },
+                )
+                .map(|x| x.trim_end().to_string())
+                .filter(|x| !x.is_empty())
+                .collect::<Vec<_>>()
+                .join("\n")
+        })
+        .unwrap_or_default()
+}
+
+fn is_selected(index: usize, cell: &Value, selector: &Selector) -> bool {
+    match selector {
+        Selector::Index(i) => *i == index + 1,
+        Selector::Tag(tag) => cell
+            .get("metadata")
+            .and_then(|x| x.get("tags"))
+            .and_then(|x| x.as_array())
+            .map(|tags| tags.iter().any(|x| x.as_str() == Some(tag)))
+            .unwrap_or(false),
+        Selector::Id(id) => cell.get("id").and_then(|x| x.as_str()) == Some(id),
+    }
+}
+
+///
+/// Extracts cells from a Jupyter notebook.
+///
+/// # Parameters
+/// * `input` The contents of an `.ipynb` file
+/// * `selectors` Cells matching any selector are included, in notebook order
+/// * `show` Whether to include the source, text outputs, or both
+///
+pub(crate) fn cells(input: &str, selectors: &[Selector], show: Show) -> Result<String> {
+    let notebook: Value = serde_json::from_str(input).context("Input is not a valid notebook")?;
+    let cells = notebook
+        .get("cells")
+        .and_then(|x| x.as_array())
+        .context("Notebook has no cells")?;
+    if let Some(missing) = selectors
+        .iter()
+        .find(|s| !cells.iter().enumerate().any(|(i, c)| is_selected(i, c, s)))
+    {
+        return Err(anyhow::anyhow!("No notebook cell matches {:?}", missing));
+    }
+    Ok(cells
+        .iter()
+        .enumerate()
+        .filter(|(i, cell)| selectors.iter().any(|s| is_selected(*i, cell, s)))
+        .map(|(_, cell)| {
+            let source = text(cell.get("source")).trim_end().to_string();
+            match show {
+                Show::Source => source,
+                Show::Outputs => outputs(cell),
+                Show::Both => match outputs(cell) {
+                    x if x.is_empty() => source,
+                    x => format!("{}\n\n{}", source, x),
+                },
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n"))
+}
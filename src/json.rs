@@ -0,0 +1,131 @@
+use anyhow::{Context, Result};
+use serde_json::{Map, Value};
+
+fn parse(input: &str) -> Result<Value> {
+    serde_json::from_str(input).context("Input is not valid JSON")
+}
+
+///
+/// Finds the value at a dotted `path` (e.g. `data.items.0`).
+/// Numeric segments index into arrays. An empty path (or `.`) selects the whole document.
+///
+fn select<'a>(value: &'a Value, path: &str) -> Result<&'a Value> {
+    path.split('.')
+        .filter(|x| !x.is_empty())
+        .try_fold(value, |value, key| {
+            match value {
+                Value::Object(map) => map.get(key),
+                Value::Array(list) => key.parse::<usize>().ok().and_then(|i| list.get(i)),
+                _ => None,
+            }
+            .with_context(|| format!("Could not find '{}' in JSON path '{}'", key, path))
+        })
+}
+
+///
+/// Returns the value at `path` in the JSON `input`.
+/// Strings are returned without quotes, anything else is pretty-printed as JSON
+/// so it can be piped into another JSON command.
+///
+pub(crate) fn get(input: &str, path: &str) -> Result<String> {
+    let value = parse(input)?;
+    Ok(match select(&value, path)? {
+        Value::String(x) => x.clone(),
+        x => serde_json::to_string_pretty(x)?,
+    })
+}
+
+/// Flattens nested objects into `(dotted.key, value)` pairs
+fn flatten<'a>(prefix: &str, map: &'a Map<String, Value>, out: &mut Vec<(String, &'a Value)>) {
+    for (key, value) in map {
+        let key = match prefix {
+            "" => key.to_string(),
+            _ => format!("{}.{}", prefix, key),
+        };
+        match value {
+            Value::Object(inner) if !inner.is_empty() => flatten(&key, inner, out),
+            _ => out.push((key, value)),
+        }
+    }
+}
+
+/// Formats a single value so it is safe to put inside a table cell
+fn cell(value: &Value) -> String {
+    let text = match value {
+        Value::Null => String::new(),
+        Value::String(x) => x.clone(),
+        x => x.to_string(),
+    };
+    text.replace('|', "\\|")
+        .replace("\r\n", "<br>")
+        .replace('\n', "<br>")
+}
+
+///
+/// Renders a JSON array of objects as a markdown table.
+///
+/// # Parameters
+/// * `input` A JSON array of objects
+/// * `columns` The columns to show, in order. If empty, every key is used in the order it is first found.
+///
+pub(crate) fn table(input: &str, columns: &[&str]) -> Result<String> {
+    let value = parse(input)?;
+    let rows = value
+        .as_array()
+        .context("Expected a JSON array of objects")?
+        .iter()
+        .enumerate()
+        .map(|(i, row)| {
+            let row = row
+                .as_object()
+                .with_context(|| format!("Item {} of JSON array is not an object", i))?;
+            let mut fields = vec![];
+            flatten("", row, &mut fields);
+            Ok(fields)
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut headers: Vec<String> = vec![];
+    for (key, _) in rows.iter().flatten() {
+        if !headers.contains(key) {
+            headers.push(key.clone());
+        }
+    }
+    if !columns.is_empty() {
+        if let Some(missing) = columns.iter().find(|x| !headers.iter().any(|h| h == *x)) {
+            return Err(anyhow::anyhow!(
+                "Column '{}' not found in JSON records",
+                missing
+            ));
+        }
+        headers = columns.iter().map(|x| x.to_string()).collect();
+    }
+    if headers.is_empty() {
+        return Err(anyhow::anyhow!("No columns found in JSON records"));
+    }
+
+    let line = |cells: Vec<String>| format!("| {} |", cells.join(" | "));
+    let mut lines = vec![
+        line(
+            headers
+                .iter()
+                .map(|x| cell(&Value::String(x.clone())))
+                .collect(),
+        ),
+        line(headers.iter().map(|_| "---".to_string()).collect()),
+    ];
+    for row in &rows {
+        lines.push(line(
+            headers
+                .iter()
+                .map(|h| {
+                    row.iter()
+                        .find(|(key, _)| key == h)
+                        .map(|(_, value)| cell(value))
+                        .unwrap_or_default()
+                })
+                .collect(),
+        ));
+    }
+    Ok(lines.join("\n"))
+}
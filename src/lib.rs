@@ -6,13 +6,27 @@ use std::fs::{read_to_string, File};
 use std::io::Write;
 use std::path::{Path, PathBuf};
 pub use structopt::StructOpt;
+mod cache;
 mod config;
+mod date;
+mod diff;
+mod exec;
+mod git;
+mod ipynb;
+mod json;
+mod lines;
+mod markdown;
 mod parse;
+mod pattern;
+mod render;
+mod snippet;
 use crate::config::ConfigAndPath;
 use crate::parse::Parser;
 pub use crate::{
     config::{Config, OutputTo},
-    parse::ParserConfig,
+    exec::ExecConfig,
+    parse::{CommandTags, ParserConfig},
+    render::Renderer,
 };
 
 #[cfg(test)]
@@ -134,6 +148,16 @@ pub struct Args {
     ///
     #[structopt(short, long, help = "Print output to stdout")]
     print: bool,
+
+    ///
+    /// Runs every command again, instead of using the output in `cache_dir`.
+    /// The new output is still saved to the cache.
+    ///
+    #[structopt(
+        long = "no-cache",
+        help = "Run commands again instead of reading cached output, and cache the new output"
+    )]
+    no_cache: bool,
 }
 
 ///
@@ -150,7 +174,7 @@ pub fn transform_files_with_args(args: Args, config: Option<ConfigAndPath>) -> R
     let mut out_dir: Option<PathBuf> = None;
     if let Some(x) = args.working_dir.first() {
         if x.exists() {
-            std::env::set_current_dir(&x)
+            std::env::set_current_dir(x)
                 .with_context(|| format!("Could not set working directory: {:?}", &x))?;
         }
     }
@@ -177,6 +201,9 @@ pub fn transform_files_with_args(args: Args, config: Option<ConfigAndPath>) -> R
     if let Some(x) = args.base_dir {
         parser.base_dir = x;
     }
+    if args.no_cache {
+        parser.refresh_cache = true;
+    }
     transform_files(
         parser,
         &files,
@@ -209,36 +236,39 @@ pub fn transform_files<P: AsRef<Path>>(
     prefs: OutputTo,
 ) -> Result<Vec<String>> {
     let (read_only, print, out_dir) = (prefs.read_only, prefs.print, prefs.out_dir);
-    Ok(files
+    files
         .iter()
         .map(|file| {
             let file = file.as_ref();
             print!(" {}", &file.to_str().unwrap_or_default());
-            let file_parser = Parser::new(parser.clone(), read_to_string(file.clone())?);
+            let out_path = out_dir.as_ref().map(|path| match path.is_dir() {
+                true => {
+                    let name = file.file_name().and_then(|x| x.to_str()).unwrap_or("out");
+                    path.join(name)
+                }
+                false => path.clone(),
+            });
+            let file_parser = Parser::new(parser.clone(), read_to_string(file)?)
+                .with_path(out_path.as_deref().unwrap_or(file));
             let res = file_parser.parse()?;
             if !read_only {
-                match &out_dir {
+                match &out_path {
                     Some(path) => {
-                        let mut path = path.clone();
-                        if path.is_dir() {
-                            let name = file.file_name().and_then(|x| x.to_str()).unwrap_or("out");
-                            path = path.join(name)
-                        }
                         if path.is_file() {
                             // Check if contents has changed
-                            let contents = read_to_string(&path)?;
+                            let contents = read_to_string(path)?;
                             if contents == res {
                                 println!(" [[No changes]]");
                                 return Ok(res); // Next file
                             }
                         }
-                        let mut f = File::create(&path)?;
+                        let mut f = File::create(path)?;
                         f.write_all(res.as_bytes())?;
                         println!(" [[Updated!]]")
                     }
                     _ => {
                         if res != file_parser.content {
-                            let mut f = File::create(&file)?;
+                            let mut f = File::create(file)?;
                             f.write_all(res.as_bytes())?;
                             println!(" [[Updated!]]")
                         } else {
@@ -252,7 +282,7 @@ pub fn transform_files<P: AsRef<Path>>(
             }
             Ok(res)
         })
-        .collect::<Result<Vec<_>>>()?)
+        .collect::<Result<Vec<_>>>()
 }
 
 ///
@@ -290,7 +320,7 @@ pub fn walk_transform(mut args: Args) -> Result<Vec<Vec<String>>> {
 
     let config: Option<ConfigAndPath> = if let Some(path) = &args.config {
         Some(ConfigAndPath {
-            config: Config::try_from_path(&path)?,
+            config: Config::try_from_path(path)?,
             path: path.to_path_buf(),
         })
     } else if !args.ignore_config {
@@ -312,7 +342,7 @@ pub fn walk_transform(mut args: Args) -> Result<Vec<Vec<String>>> {
             .depend_dirs
             .iter()
             .map(|x| parent.join(x))
-            .chain(subdirs.into_iter())
+            .chain(subdirs)
             .chain(x.config.next_dirs.iter().map(|x| parent.join(x)))
             .collect();
         Some(x)
@@ -0,0 +1,287 @@
+use anyhow::{Context, Result};
+
+///
+/// Parses a line number as written in a command.
+/// Positive numbers are 1-based, negative numbers count back from the last line (`-1`).
+///
+fn parse_index(arg: &str) -> Result<isize> {
+    match arg.parse::<isize>() {
+        Ok(0) => Err(anyhow::anyhow!(
+            "Invalid line '0' (lines start at 1, or -1 for the last line)"
+        )),
+        x => x.with_context(|| format!("Invalid line: '{}'", arg)),
+    }
+}
+
+/// Converts a line number to a 0-based index into `count` lines
+fn resolve(index: isize, count: usize) -> Result<usize> {
+    let resolved = if index > 0 {
+        index as usize - 1
+    } else {
+        count.checked_sub(index.unsigned_abs()).unwrap_or(count)
+    };
+    if resolved >= count {
+        return Err(anyhow::anyhow!(
+            "Line {} is out of range (input has {} lines)",
+            index,
+            count
+        ));
+    }
+    Ok(resolved)
+}
+
+///
+/// Parses a single line number (`7`, `-1`) or an inclusive range (`1-3`, `-3--1`).
+/// Returns the first and last 0-based index.
+///
+fn parse_range(arg: &str, count: usize) -> Result<(usize, usize)> {
+    // Skip a leading '-' so negative numbers aren't mistaken for a range
+    let split = arg
+        .char_indices()
+        .skip(1)
+        .find(|(_, c)| *c == '-')
+        .map(|(i, _)| i);
+    let (first, last) = match split {
+        Some(i) => (&arg[..i], &arg[i + 1..]),
+        None => (arg, arg),
+    };
+    Ok((
+        resolve(parse_index(first)?, count)?,
+        resolve(parse_index(last)?, count)?,
+    ))
+}
+
+///
+/// Returns the indices of lines from `first` to `last` (inclusive).
+///
+/// # Parameters
+/// * `count` The number of lines in the input
+/// * `first` The first line to include (defaults to the start of the input)
+/// * `last` The last line to include (defaults to the end of the input)
+///
+pub(crate) fn range(count: usize, first: Option<&str>, last: Option<&str>) -> Result<Vec<usize>> {
+    if count == 0 && first.is_none() && last.is_none() {
+        return Ok(vec![]);
+    }
+    let first = match first {
+        Some(x) => resolve(parse_index(x)?, count)?,
+        None => 0,
+    };
+    let last = match last {
+        Some(x) => resolve(parse_index(x)?, count)?,
+        None => count.saturating_sub(1),
+    };
+    if first > last {
+        return Err(anyhow::anyhow!(
+            "Invalid line range: line {} comes after line {}",
+            first + 1,
+            last + 1
+        ));
+    }
+    Ok((first..=last).collect())
+}
+
+///
+/// Returns the indices of a list of line numbers and ranges, e.g. `["1-3", "7", "-2--1"]`.
+/// Lines are returned in the order given, so `3-1` returns lines 3, 2 and 1.
+///
+pub(crate) fn list(count: usize, items: &[&str]) -> Result<Vec<usize>> {
+    let mut indices = vec![];
+    for item in items {
+        match parse_range(item, count)? {
+            (first, last) if first <= last => indices.extend(first..=last),
+            (first, last) => indices.extend((last..=first).rev()),
+        }
+    }
+    Ok(indices)
+}
+
+/// Whether the lines matching the `between` patterns are kept
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Include {
+    Both,
+    Start,
+    End,
+    Neither,
+}
+
+impl Include {
+    pub(crate) fn from_arg(arg: &str) -> Result<Self> {
+        Ok(match arg {
+            "both" => Include::Both,
+            "start" => Include::Start,
+            "end" => Include::End,
+            "none" => Include::Neither,
+            x => {
+                return Err(anyhow::anyhow!(
+                    "Invalid 'include' option: '{}' (expected both, start, end or none)",
+                    x
+                ))
+            }
+        })
+    }
+}
+
+///
+/// Returns the indices of lines from a line matching `start` to the next line matching `end`.
+///
+/// # Parameters
+/// * `lines` The input lines
+/// * `start` Pattern for the first line
+/// * `end` Pattern for the last line. If not given, lines are kept until the end of the input.
+/// * `nth` Which occurrence of `start` to use (1-based)
+/// * `include` Whether the matching start and end lines are kept
+///
+pub(crate) fn between(
+    lines: &[&str],
+    start: &regex::Regex,
+    end: Option<&regex::Regex>,
+    nth: usize,
+    include: Include,
+) -> Result<Vec<usize>> {
+    let first = lines
+        .iter()
+        .enumerate()
+        .filter(|(_, x)| start.is_match(x))
+        .nth(
+            nth.checked_sub(1)
+                .context("Occurrence must be at least 1")?,
+        )
+        .map(|(i, _)| i)
+        .with_context(|| match nth {
+            1 => format!("No line matches '{}'", start),
+            _ => format!("Fewer than {} lines match '{}'", nth, start),
+        })?;
+    let last = match end {
+        Some(end) => lines
+            .iter()
+            .enumerate()
+            .skip(first + 1)
+            .find(|(_, x)| end.is_match(x))
+            .map(|(i, _)| i)
+            .with_context(|| format!("No line matches '{}' after line {}", end, first + 1))?,
+        None => lines.len() - 1,
+    };
+    let first = match include {
+        Include::Both | Include::Start => first,
+        _ => first + 1,
+    };
+    let last = match (include, end) {
+        (Include::Both, _) | (Include::End, _) | (_, None) => last + 1,
+        _ => last,
+    };
+    Ok((first..last).collect())
+}
+
+///
+/// Returns the indices of lines matching `pattern`, including `before` and `after` lines of context.
+/// If `invert` is true, the matching lines (and their context) are dropped instead.
+///
+pub(crate) fn grep(
+    lines: &[&str],
+    pattern: &regex::Regex,
+    invert: bool,
+    before: usize,
+    after: usize,
+) -> Vec<usize> {
+    let mut marked = vec![false; lines.len()];
+    for (i, _) in lines
+        .iter()
+        .enumerate()
+        .filter(|(_, x)| pattern.is_match(x))
+    {
+        let last = (i + after).min(lines.len() - 1);
+        for x in &mut marked[i.saturating_sub(before)..=last] {
+            *x = true;
+        }
+    }
+    marked
+        .into_iter()
+        .enumerate()
+        .filter(|(_, x)| *x != invert)
+        .map(|(i, _)| i)
+        .collect()
+}
+
+/// The width of a line's leading whitespace, with tabs advancing to the next multiple of `tab_width`
+fn indent_width(line: &str, tab_width: usize) -> usize {
+    line.chars()
+        .take_while(|x| x.is_whitespace())
+        .fold(0, |col, x| match x {
+            '\t' => (col / tab_width + 1) * tab_width,
+            _ => col + 1,
+        })
+}
+
+/// Removes `width` columns of leading whitespace from `line`
+fn remove_indent(line: &str, width: usize, tab_width: usize) -> String {
+    let mut col = 0;
+    for (i, x) in line.char_indices() {
+        if col >= width || !x.is_whitespace() {
+            // A tab may have overshot the width, so pad with spaces to keep alignment
+            return format!("{}{}", " ".repeat(col - width), &line[i..]);
+        }
+        col = match x {
+            '\t' => (col / tab_width + 1) * tab_width,
+            _ => col + 1,
+        };
+    }
+    String::new()
+}
+
+///
+/// Removes the leading whitespace common to every non-blank line.
+/// Blank lines are emptied.
+///
+pub(crate) fn dedent(lines: &[&str], tab_width: usize) -> Vec<String> {
+    let width = lines
+        .iter()
+        .filter(|x| !x.trim().is_empty())
+        .map(|x| indent_width(x, tab_width))
+        .min()
+        .unwrap_or(0);
+    lines
+        .iter()
+        .map(|x| match x.trim().is_empty() {
+            true => String::new(),
+            false => remove_indent(x, width, tab_width),
+        })
+        .collect()
+}
+
+/// The default marker for hidden lines, as used in rustdoc examples
+pub(crate) static DEFAULT_HIDDEN_MARKER: &str = "# ";
+
+///
+/// Returns true if `line` is hidden by `marker`, ignoring leading whitespace.
+/// A line containing only the marker (without trailing whitespace) is also hidden, e.g. `#`.
+///
+pub(crate) fn is_hidden(line: &str, marker: &str) -> bool {
+    let line = line.trim_start();
+    line.starts_with(marker) || (line == marker.trim_end() && !line.is_empty())
+}
+
+///
+/// Removes the hidden line `marker` from the start of `line`, keeping its indentation.
+/// Lines that aren't hidden are returned unchanged.
+///
+pub(crate) fn unhide(line: &str, marker: &str) -> String {
+    if !is_hidden(line, marker) {
+        return line.to_string();
+    }
+    let indent = line.len() - line.trim_start().len();
+    let rest = &line[indent..];
+    let rest = rest.strip_prefix(marker).unwrap_or_default();
+    format!("{}{}", &line[..indent], rest)
+}
+
+///
+/// Rustdoc uses `##` to start a line with a literal `#`, so remove the escape
+///
+pub(crate) fn unescape_hidden(line: &str) -> String {
+    let indent = line.len() - line.trim_start().len();
+    match line[indent..].starts_with("##") {
+        true => format!("{}{}", &line[..indent], &line[indent + 1..]),
+        false => line.to_string(),
+    }
+}
@@ -0,0 +1,344 @@
+use anyhow::Result;
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+
+///
+/// Returns true for each line that is part of a fenced code block, including the fences.
+/// Headings and links inside code blocks are left unchanged.
+///
+fn code_lines(lines: &[&str]) -> Vec<bool> {
+    let mut fence: Option<(char, usize)> = None;
+    lines
+        .iter()
+        .map(|line| {
+            let trimmed = line.trim_start();
+            let marker = trimmed.chars().next().filter(|x| *x == '`' || *x == '~');
+            let len = marker.map_or(0, |x| trimmed.chars().take_while(|c| *c == x).count());
+            match (fence, marker) {
+                (None, Some(x)) if len >= 3 => fence = Some((x, len)),
+                // A closing fence has no info string and is at least as long as the opening fence
+                (Some((x, open)), Some(y))
+                    if x == y && len >= open && trimmed[len..].trim().is_empty() =>
+                {
+                    fence = None;
+                    return true;
+                }
+                _ => {}
+            }
+            fence.is_some()
+        })
+        .collect()
+}
+
+/// The level of an ATX heading (`## Heading`) and the text after its `#` characters
+fn atx_heading(line: &str) -> Option<(usize, &str)> {
+    let indent = line.len() - line.trim_start_matches(' ').len();
+    if indent > 3 {
+        return None;
+    }
+    let line = &line[indent..];
+    let level = line.chars().take_while(|x| *x == '#').count();
+    let rest = &line[level..];
+    match (1..=6).contains(&level) && (rest.is_empty() || rest.starts_with([' ', '\t'])) {
+        true => Some((level, rest)),
+        false => None,
+    }
+}
+
+/// The level of a setext heading underline (`===` or `---`)
+fn setext_level(line: &str) -> Option<usize> {
+    let line = line.trim();
+    match line.chars().next() {
+        Some('=') if line.chars().all(|x| x == '=') => Some(1),
+        Some('-') if line.chars().all(|x| x == '-') => Some(2),
+        _ => None,
+    }
+}
+
+/// The number of lines in the YAML front matter at the start of a document, between `---` lines
+fn front_matter(lines: &[&str]) -> usize {
+    match lines.first().map(|x| x.trim_end()) {
+        Some("---") => lines
+            .iter()
+            .skip(1)
+            .position(|x| matches!(x.trim_end(), "---" | "..."))
+            .map_or(0, |i| i + 2),
+        _ => 0,
+    }
+}
+
+/// Returns true if `line` starts an HTML block, such as a comment or a tag
+fn html_line(line: &str) -> bool {
+    let line = line.trim_start();
+    let rest = line.strip_prefix('<').unwrap_or_default();
+    line.starts_with("<!--") || rest.starts_with(|x: char| x.is_ascii_alphabetic() || x == '/')
+}
+
+///
+/// Changes the level of every heading by `shift`, keeping levels between 1 and 6.
+/// Setext headings (underlined with `===` or `---`) are converted to `#` headings.
+/// Front matter at the start of `lines` is unchanged.
+/// Returns the new lines, and the index of each new line in `lines`.
+///
+pub(crate) fn shift_headings(lines: &[&str], shift: isize) -> (Vec<String>, Vec<usize>) {
+    let code = code_lines(lines);
+    let front_matter = front_matter(lines);
+    let shifted = |level: usize| (level as isize + shift).clamp(1, 6) as usize;
+    let mut output: Vec<String> = vec![];
+    let mut indices = vec![];
+    for (i, line) in lines.iter().enumerate() {
+        if code[i] || i < front_matter {
+            output.push(line.to_string());
+        } else if let Some((level, rest)) = atx_heading(line) {
+            output.push(format!("{}{}", "#".repeat(shifted(level)), rest));
+        } else if let Some(level) = setext_level(line).filter(|_| {
+            // The underline must follow a paragraph line, otherwise `---` is a horizontal rule
+            i > front_matter
+                && !code[i - 1]
+                && !lines[i - 1].trim().is_empty()
+                && atx_heading(lines[i - 1]).is_none()
+                && !html_line(lines[i - 1])
+                && indices.last() == Some(&(i - 1))
+        }) {
+            let text = output.pop().unwrap_or_default();
+            output.push(format!("{} {}", "#".repeat(shifted(level)), text.trim()));
+            continue;
+        } else {
+            output.push(line.to_string());
+        }
+        indices.push(i);
+    }
+    (output, indices)
+}
+
+/// Returns true if `url` can't be rewritten, because it is absolute or links inside the document
+fn is_absolute(url: &str) -> bool {
+    let scheme = url.find(':').map(|i| &url[..i]).filter(|x| {
+        !x.is_empty()
+            && x.chars()
+                .all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c))
+    });
+    url.is_empty() || url.starts_with(['#', '/', '\\']) || scheme.is_some()
+}
+
+/// Resolves `.` and `..` in a path without accessing the file system
+fn normalize(path: &Path) -> PathBuf {
+    let mut output = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir if output.file_name().is_some() => {
+                output.pop();
+            }
+            x => output.push(x),
+        }
+    }
+    output
+}
+
+/// The path to `to` from the directory `from`. Both paths must be absolute.
+fn relative_path(from: &Path, to: &Path) -> PathBuf {
+    let from = normalize(from);
+    let to = normalize(to);
+    let common = from
+        .components()
+        .zip(to.components())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let mut output = PathBuf::new();
+    for _ in from.components().skip(common) {
+        output.push("..");
+    }
+    for x in to.components().skip(common) {
+        output.push(x);
+    }
+    output
+}
+
+///
+/// Rewrites a URL relative to the directory `from` to be relative to the directory `to`.
+/// Absolute URLs and links to anchors are returned unchanged.
+///
+pub(crate) fn rewrite_url(url: &str, from: &Path, to: &Path) -> Result<String> {
+    if is_absolute(url) {
+        return Ok(url.to_string());
+    }
+    let cwd = std::env::current_dir()?;
+    let (path, suffix) = url.split_at(url.find(['#', '?']).unwrap_or(url.len()));
+    let target = cwd.join(from).join(path);
+    let relative = relative_path(&cwd.join(to), &target);
+    let relative = relative.to_string_lossy().replace('\\', "/");
+    Ok(match relative.is_empty() {
+        true => format!(".{}", suffix),
+        false => format!("{}{}", relative, suffix),
+    })
+}
+
+///
+/// Applies `rewrite` to the URL of every link and image matched by `pattern`,
+/// where the URL is the first capture group that matched.
+/// Links inside fenced code blocks are unchanged.
+///
+pub(crate) fn rewrite_links<F: Fn(&str) -> Result<String>>(
+    lines: &[&str],
+    pattern: &regex::Regex,
+    rewrite: F,
+) -> Result<Vec<String>> {
+    let code = code_lines(lines);
+    lines
+        .iter()
+        .zip(code)
+        .map(|(line, code)| {
+            if code {
+                return Ok(line.to_string());
+            }
+            let mut output = String::new();
+            let mut last = 0;
+            for captures in pattern.captures_iter(line) {
+                if let Some(url) = captures.iter().skip(1).flatten().next() {
+                    output.push_str(&line[last..url.start()]);
+                    output.push_str(&rewrite(url.as_str())?);
+                    last = url.end();
+                }
+            }
+            output.push_str(&line[last..]);
+            Ok(output)
+        })
+        .collect()
+}
+
+/// Matches the URLs of inline links and images, reference definitions and HTML `src`/`href` attributes.
+/// Footnote definitions (`[^1]: text`) aren't links.
+pub(crate) static LINK_PATTERN: &str = r#"(?:\]\(\s*<?([^)\s>]+))|(?:^\s{0,3}\[[^\]^][^\]]*\]:\s*<?([^\s>]+))|(?:\b(?:src|href)="([^"]+)")"#;
+
+///
+/// Returns the level and text of each heading, ignoring headings in fenced code blocks
+/// and front matter.
+///
+pub(crate) fn headings(lines: &[&str]) -> Vec<(usize, String)> {
+    let (lines, _) = shift_headings(lines, 0);
+    let code = code_lines(&lines.iter().map(|x| x.as_str()).collect::<Vec<_>>());
+    lines
+        .iter()
+        .zip(code)
+        .filter(|(_, code)| !code)
+        .filter_map(|(line, _)| atx_heading(line))
+        .map(|(level, text)| {
+            // Remove the optional closing `#` characters
+            let text = text.trim();
+            let closing = text.trim_end_matches('#');
+            let text = match closing.is_empty() || closing.ends_with([' ', '\t']) {
+                true => closing.trim_end(),
+                false => text,
+            };
+            (level, text.to_string())
+        })
+        .collect()
+}
+
+/// Replaces links in heading text with the link text
+fn strip_links(text: &str) -> String {
+    let mut output = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find('[') {
+        let close = rest[start..].find("](").map(|i| start + i);
+        let end = close.and_then(|i| rest[i..].find(')').map(|j| i + j));
+        match (close, end) {
+            (Some(close), Some(end)) => {
+                let before = rest[..start].trim_end_matches('!');
+                output.push_str(before);
+                output.push_str(&rest[start + 1..close]);
+                rest = &rest[end + 1..];
+            }
+            _ => break,
+        }
+    }
+    output.push_str(rest);
+    output
+}
+
+///
+/// Creates the anchor GitHub generates for a heading.
+/// Letters are lowercased, spaces become `-` and other punctuation is removed.
+///
+fn slug(text: &str) -> String {
+    strip_links(text)
+        .to_lowercase()
+        .chars()
+        .filter_map(|x| match x {
+            ' ' => Some('-'),
+            x if x.is_alphanumeric() || x == '-' || x == '_' => Some(x),
+            _ => None,
+        })
+        .collect()
+}
+
+///
+/// Creates a nested list of links to `headings`.
+/// Repeated anchors are numbered (`#example`, `#example-1`) as on GitHub.
+///
+pub(crate) fn toc(headings: &[(usize, String)]) -> String {
+    let top = headings.iter().map(|(level, _)| *level).min().unwrap_or(1);
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    headings
+        .iter()
+        .map(|(level, text)| {
+            let slug = slug(text);
+            let count = seen.entry(slug.clone()).or_insert(0);
+            let anchor = match *count {
+                0 => slug,
+                n => format!("{}-{}", slug, n),
+            };
+            *count += 1;
+            format!(
+                "{}* [{}](#{})",
+                "    ".repeat(level - top),
+                strip_links(text),
+                anchor
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Returns the indentation of a list item, or `None` if `line` isn't a list item
+fn list_item(line: &str) -> Option<usize> {
+    let trimmed = line.trim_start();
+    let indent = line.len() - trimmed.len();
+    let digits = trimmed.chars().take_while(char::is_ascii_digit).count();
+    let marker = match digits {
+        0 => trimmed.starts_with(['*', '-', '+']),
+        _ => trimmed[digits..].starts_with(['.', ')']),
+    };
+    let rest = trimmed.get(digits + 1..).unwrap_or_default();
+    match marker && (rest.is_empty() || rest.starts_with(' ')) {
+        true => Some(indent),
+        false => None,
+    }
+}
+
+///
+/// Returns the indices of lines in a nested list that are at most `depth` levels deep.
+/// Lines that continue a list item are kept or removed with the item.
+///
+pub(crate) fn max_depth(lines: &[&str], depth: usize) -> Vec<usize> {
+    let mut indents: Vec<usize> = vec![];
+    let mut keep = true;
+    lines
+        .iter()
+        .enumerate()
+        .filter(|(_, line)| {
+            if let Some(indent) = list_item(line) {
+                while matches!(indents.last(), Some(x) if *x > indent) {
+                    indents.pop();
+                }
+                if indents.last() != Some(&indent) {
+                    indents.push(indent);
+                }
+                keep = indents.len() <= depth;
+            }
+            keep
+        })
+        .map(|(i, _)| i)
+        .collect()
+}
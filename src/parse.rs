@@ -1,9 +1,14 @@
-use crate::config::{DEFAULT_END_COMMAND, DEFAULT_TAG_BEGIN, DEFAULT_TAG_END};
+use crate::config::{default_languages, DEFAULT_END_COMMAND, DEFAULT_TAG_BEGIN, DEFAULT_TAG_END};
+use crate::exec::{self, ExecConfig};
+use crate::pattern::{self, RegexCache};
+use crate::render::{self, Renderer};
+use crate::snippet::Snippet;
+use crate::{cache, date, diff, git, ipynb, json, lines, markdown};
 use anyhow::{Context, Result};
 use nom::bytes::complete::{take, take_until, take_while};
 use nom::character::complete::{none_of, space0, space1};
 use nom::character::is_alphanumeric;
-use nom::combinator::map;
+use nom::combinator::{map, verify};
 use nom::multi::{count, fold_many0, many0, many_till, separated_nonempty_list};
 use nom::sequence::{delimited, pair, separated_pair, tuple};
 use nom::{
@@ -12,7 +17,8 @@ use nom::{
     character::complete::char,
     IResult,
 };
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 
 #[derive(Debug, Clone, PartialEq)]
 pub(crate) struct Command<'a> {
@@ -49,7 +55,10 @@ fn wrapped_string(input: &str) -> IResult<&str, &str> {
     Ok((i, &input[offset..offset + inner.len()]))
 }
 
-static EXTRA_STRING_CHARS: &'static [u8] = "/\\-_.".as_bytes();
+static EXTRA_STRING_CHARS: &[u8] = "/\\-_.=+@".as_bytes();
+
+/// The source that generates a table of contents for the document
+const TOC_SOURCE: &str = "@toc";
 
 fn is_string_char(i: char) -> bool {
     if i.is_ascii() {
@@ -77,7 +86,7 @@ fn command_args(i: &str) -> IResult<&str, Vec<&str>> {
     Ok((i, res))
 }
 
-fn command<'a>(i: &'a str) -> IResult<&'a str, Command> {
+fn command<'a>(i: &'a str) -> IResult<&'a str, Command<'a>> {
     alt((
         map(
             separated_pair(
@@ -93,10 +102,27 @@ fn command<'a>(i: &'a str) -> IResult<&'a str, Command> {
     ))(i)
 }
 
+///
+/// The first command of a block, which may be a generated source (`@name`) or glob pattern
+/// with space-separated arguments and no colon, e.g. `@diff "v1.rs" "v2.rs"`
+///
+fn source_command<'a>(i: &'a str) -> IResult<&'a str, Command<'a>> {
+    alt((
+        map(
+            verify(
+                separated_pair(maybe_wrapped_string, space1, command_args),
+                |(source, _): &(&str, Vec<&str>)| source.starts_with('@') || is_glob(source),
+            ),
+            |(source, args)| Command::new(source, args),
+        ),
+        command,
+    ))(i)
+}
+
 fn command_block<'a>(tags: &'a CommandTags, input: &'a str) -> IResult<&'a str, CommandSec<'a>> {
     let start_remaining = input.len();
     let (i, _open) = tag(tags.opening.as_str())(input)?;
-    let (i, command_1) = delimited(space0, command, space0)(i)?;
+    let (i, command_1) = delimited(space0, source_command, space0)(i)?;
     let (rest, (mut other_commands, _end)) = many_till(
         delimited(delimited(space0, char('|'), space0), command, space0),
         tag(tags.closing.as_str()),
@@ -150,11 +176,33 @@ impl CommandTags {
     }
 }
 
+///
+/// Settings for parsing documents.
+/// New fields may be added, so create it with `ParserConfig::new` or `ParserConfig::default`
+/// and set the fields to change.
+///
 #[derive(Clone, Debug)]
+#[non_exhaustive]
 pub struct ParserConfig {
     pub tags: CommandTags,
     pub end_command: String,
     pub base_dir: PathBuf,
+    /// Languages used by the `code` command, keyed by file extension
+    pub languages: HashMap<String, String>,
+    /// The markdown renderer that output is written for
+    pub renderer: Renderer,
+    /// If true, code blocks are given a title of the included file path
+    pub code_title: bool,
+    /// Compiled regexes, shared by every clone of this config
+    pub(crate) regex: RegexCache,
+    /// Settings for running commands with `@exec`
+    pub exec: ExecConfig,
+    /// The directory the output of `@exec` and `@session` is cached in, if caching is enabled
+    pub cache_dir: Option<PathBuf>,
+    /// If true, commands are run again instead of reading cached output, and the new output is cached
+    pub refresh_cache: bool,
+    /// The path of the document being written, set with `Parser::with_path`
+    pub(crate) document: Option<PathBuf>,
 }
 
 impl Default for ParserConfig {
@@ -163,6 +211,41 @@ impl Default for ParserConfig {
             tags: CommandTags::new(DEFAULT_TAG_BEGIN, DEFAULT_TAG_END),
             end_command: DEFAULT_END_COMMAND.to_string(),
             base_dir: std::env::current_dir().unwrap(),
+            languages: default_languages(),
+            renderer: Renderer::default(),
+            code_title: false,
+            regex: RegexCache::default(),
+            exec: ExecConfig::default(),
+            cache_dir: None,
+            refresh_cache: false,
+            document: None,
+        }
+    }
+}
+
+impl ParserConfig {
+    ///
+    /// Creates a config with the given tags, end command and base directory.
+    /// Every other field has its default value.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use md_inc::{CommandTags, ParserConfig};
+    /// let mut config = ParserConfig::new(CommandTags::new("<!--{", "}-->"), "end", "docs");
+    /// config.code_title = true;
+    /// ```
+    ///
+    pub fn new<S: Into<String>, P: Into<PathBuf>>(
+        tags: CommandTags,
+        end_command: S,
+        base_dir: P,
+    ) -> Self {
+        Self {
+            tags,
+            end_command: end_command.into(),
+            base_dir: base_dir.into(),
+            ..Default::default()
         }
     }
 }
@@ -193,100 +276,515 @@ pub fn escaped<'a>(input: &'a str) -> String {
         .unwrap_or_else(|_err| String::new())
 }
 
+///
+/// Command arguments split into positional arguments and `key=value` options.
+/// Only the keys a command knows about are treated as options, so other arguments
+/// (e.g. a regex containing `=`) are left as positional arguments.
+///
+pub(crate) struct Options<'a> {
+    pub positional: Vec<&'a str>,
+    pub named: Vec<(&'a str, &'a str)>,
+}
+
+impl<'a> Options<'a> {
+    pub fn parse(args: &[&'a str], keys: &[&str]) -> Self {
+        let mut positional = vec![];
+        let mut named = vec![];
+        for arg in args {
+            match arg.find('=') {
+                Some(i) if keys.contains(&&arg[..i]) => named.push((&arg[..i], &arg[i + 1..])),
+                _ => positional.push(*arg),
+            }
+        }
+        Self { positional, named }
+    }
+
+    ///
+    /// Like `parse`, but returns an error for a `key=value` argument with an unknown key.
+    /// Used by commands whose positional arguments never contain `=`, so typos aren't ignored.
+    ///
+    pub fn parse_strict(args: &[&'a str], keys: &[&str], command: &str) -> Result<Self> {
+        let options = Self::parse(args, keys);
+        match options.positional.iter().find_map(|x| x.split_once('=')) {
+            Some((key, _)) => Err(anyhow::anyhow!("Unknown '{}' option: '{}'", command, key)),
+            None => Ok(options),
+        }
+    }
+
+    /// The value of the last option named `key`
+    pub fn get(&self, key: &str) -> Option<&'a str> {
+        self.named
+            .iter()
+            .rev()
+            .find(|(k, _)| *k == key)
+            .map(|(_, v)| *v)
+    }
+
+    /// Every value of the options named `key`, in order
+    pub fn get_all(&self, key: &str) -> Vec<&'a str> {
+        self.named
+            .iter()
+            .filter(|(k, _)| *k == key)
+            .map(|(_, v)| *v)
+            .collect()
+    }
+
+    /// Parses the value of the option named `key`, or returns `default` if not set
+    pub fn parse_or<T: std::str::FromStr>(&self, key: &str, default: T) -> Result<T> {
+        match self.get(key) {
+            Some(x) => x
+                .parse()
+                .map_err(|_| anyhow::anyhow!("Invalid '{}' option: '{}'", key, x)),
+            None => Ok(default),
+        }
+    }
+}
+
+///
+/// The state available to commands while transforming an included file
+///
+pub(crate) struct TransformContext<'a> {
+    pub config: &'a ParserConfig,
+    /// The included file, as written in the command block
+    pub source: Option<&'a str>,
+}
+
+impl<'a> TransformContext<'a> {
+    pub fn new(config: &'a ParserConfig, source: Option<&'a str>) -> Self {
+        Self { config, source }
+    }
+
+    /// The language for the extension of the included file, if there is one in `languages`
+    fn language(&self) -> Option<&'a str> {
+        let extension = Path::new(self.source?).extension()?.to_str()?;
+        self.config.languages.get(extension).map(|x| x.as_str())
+    }
+}
+
+///
+/// Creates a fenced code block that is longer than any run of backticks inside `input`
+///
+fn code_block(input: &Snippet, language: &str) -> Snippet {
+    let fence = render::fence(&input.text);
+    input.wrap(&format!("{}{}\n", fence, language), &format!("\n{}", fence))
+}
+
+///
+/// The marker for omitted lines, written as a comment in `language` where possible
+///
+fn elide_marker(language: Option<&str>) -> &'static str {
+    match language.unwrap_or_default() {
+        "c" | "cpp" | "csharp" | "css" | "go" | "java" | "javascript" | "kotlin" | "php"
+        | "rust" | "swift" | "typescript" => "// ...",
+        "bash" | "python" | "ruby" | "toml" | "yaml" => "# ...",
+        "sql" => "-- ...",
+        "html" | "markdown" | "xml" => "<!-- ... -->",
+        _ => "...",
+    }
+}
+
+///
+/// Applies a command to text read from a file
+///
+#[cfg(test)]
 pub(crate) fn transform<S: AsRef<str>>(input: S, command: &Command) -> Result<String> {
-    let input = input.as_ref();
+    let input = Snippet::from_source(input.as_ref(), 1);
+    let config = ParserConfig::default();
+    Ok(transform_snippet(&TransformContext::new(&config, None), &input, command)?.text)
+}
+
+///
+/// Applies a command to a snippet, keeping track of the original line numbers
+///
+pub(crate) fn transform_snippet(
+    context: &TransformContext,
+    input: &Snippet,
+    command: &Command,
+) -> Result<Snippet> {
+    let config = context.config;
+    let text = input.text.as_str();
     let args = &command.args;
     let command = command.command;
     Ok(match command {
-        "code" => match args.first() {
-            Some(language) => format!("```{}\n{}\n```", language, input),
-            _ => format!("```\n{}\n```", input),
-        },
-        "lines" => {
-            let from_line = args
-                .get(0)
-                .map(|x| x.parse().context("Invalid 'from' line"))
-                .unwrap_or(Ok(1))?
-                - 1;
-            let to_line = args
+        "code" => {
+            let options = Options::parse(args, &["lang", "title", "highlight"]);
+            // The first argument is the language, unless `lang=` is given or it is a `key=value` attribute
+            let (language, attributes) =
+                match (options.get("lang"), options.positional.split_first()) {
+                    (Some(language), _) => (language, options.positional.as_slice()),
+                    (None, Some((language, attributes))) if !language.contains('=') => {
+                        (*language, attributes)
+                    }
+                    (None, _) => (
+                        context.language().unwrap_or_default(),
+                        options.positional.as_slice(),
+                    ),
+                };
+            let title = match options.get("title") {
+                Some(x) => Some(x).filter(|x| !x.is_empty()),
+                // Renderers without titles ignore the default title
+                None if config.code_title && config.renderer.supports_titles() => context.source,
+                None => None,
+            };
+            let highlight = options
+                .get("highlight")
+                .map(render::parse_highlight)
+                .transpose()?
+                .unwrap_or_default();
+            let info = config
+                .renderer
+                .fence_info(language, title, &highlight, attributes)?;
+            let block = code_block(input, &info);
+            match config.renderer.title_line(title) {
+                Some(line) => block.wrap(&line, ""),
+                None => block,
+            }
+        }
+        "lines" => input.select(&lines::range(
+            input.text.lines().count(),
+            args.first().copied(),
+            args.get(1).copied(),
+        )?),
+        "line" => input.select(&lines::list(input.text.lines().count(), args)?),
+        "between" => {
+            let options = Options::parse(args, &["nth", "include", "flags"]);
+            let flags = options.get("flags");
+            let start = options
+                .positional
+                .first()
+                .context("Missing start pattern")?;
+            let start = config.regex.get(start, flags)?;
+            let end = options
+                .positional
                 .get(1)
-                .map(|x| x.parse().context("Invalid 'to' line"))
-                .unwrap_or(Ok(input.len()))?;
-            (&input
-                .lines()
-                .skip(from_line)
-                .take(to_line - from_line)
-                .collect::<Vec<&str>>())
-                .join("\n")
-        }
-        "line" => args
-            .iter()
-            .map(|x| -> Result<String> {
-                let line = x.parse::<usize>().context("Invalid line")? - 1;
-                Ok(input
-                    .lines()
-                    .skip(line)
-                    .next()
-                    .context("Missing line")?
-                    .to_string())
+                .map(|x| config.regex.get(x, flags))
+                .transpose()?;
+            let nth = options.parse_or("nth", 1)?;
+            let include = match options.get("include") {
+                Some(x) => lines::Include::from_arg(x)?,
+                None => lines::Include::Both,
+            };
+            input.select(&lines::between(
+                &input.lines(),
+                &start,
+                end.as_ref(),
+                nth,
+                include,
+            )?)
+        }
+        "grep" | "grep-v" => {
+            let options = Options::parse(args, &["context", "before", "after", "flags"]);
+            let pattern = options
+                .positional
+                .first()
+                .context("Missing regex pattern")?;
+            let pattern = config.regex.get(pattern, options.get("flags"))?;
+            let context = options.parse_or("context", 0)?;
+            let before = options.parse_or("before", context)?;
+            let after = options.parse_or("after", context)?;
+            input.select(&lines::grep(
+                &input.lines(),
+                &pattern,
+                command == "grep-v",
+                before,
+                after,
+            ))
+        }
+        "dedent" => {
+            let options = Options::parse(args, &["tab-width"]);
+            let tab_width = options.parse_or("tab-width", 4)?;
+            if tab_width == 0 {
+                return Err(anyhow::anyhow!("'tab-width' must be at least 1"));
+            }
+            Snippet::new(
+                lines::dedent(&input.lines(), tab_width).join("\n"),
+                input.origins.clone(),
+            )
+        }
+        "indent" => {
+            let prefix = match args.first() {
+                Some(x) => match x.parse::<usize>() {
+                    Ok(width) => " ".repeat(width),
+                    Err(_) => escaped(x),
+                },
+                None => " ".repeat(4),
+            };
+            input.map_lines(|x| match x.is_empty() {
+                true => String::new(),
+                false => format!("{}{}", prefix, x),
             })
-            .collect::<Result<Vec<String>>>()?
-            .join("\n"),
+        }
+        "hide-lines" | "unhide" => {
+            let marker = match args.first() {
+                Some(x) => escaped(x),
+                None => lines::DEFAULT_HIDDEN_MARKER.to_string(),
+            };
+            let rustdoc = marker == lines::DEFAULT_HIDDEN_MARKER;
+            let output = match command {
+                "hide-lines" => input.select(
+                    &input
+                        .lines()
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, x)| !lines::is_hidden(x, &marker))
+                        .map(|(i, _)| i)
+                        .collect::<Vec<_>>(),
+                ),
+                _ => input.map_lines(|x| lines::unhide(x, &marker)),
+            };
+            match rustdoc {
+                true => output.map_lines(lines::unescape_hidden),
+                false => output,
+            }
+        }
         "line-numbers" => {
-            let separator = args.get(0).unwrap_or(&": ");
-            let width = args
+            let options = Options::parse(args, &["start"]);
+            let separator = options.positional.first().unwrap_or(&": ");
+            let numbers = match options.get("start") {
+                Some(x) => {
+                    let start = x.parse::<usize>().context("Invalid 'start' option")?;
+                    (start..start + input.text.lines().count())
+                        .map(Some)
+                        .collect()
+                }
+                // Number generated text from 1
+                None if input.origins.iter().all(Option::is_none) => {
+                    (1..=input.text.lines().count()).map(Some).collect()
+                }
+                None => input.origins.clone(),
+            };
+            let width = options
+                .positional
                 .get(1)
                 .and_then(|x| x.parse::<usize>().ok())
-                .unwrap_or_else(|| input.lines().count().to_string().len());
-
-            input
-                .lines()
-                .enumerate()
-                .map(|(i, x)| match i {
-                    0 => format!("{:>w$}{}{}", i + 1, separator, x, w = width),
-                    _ => format!("\n{:>w$}{}{}", i + 1, separator, x, w = width),
-                })
-                .collect::<String>()
+                .unwrap_or_else(|| {
+                    numbers
+                        .iter()
+                        .flatten()
+                        .max()
+                        .map(|x| x.to_string().len())
+                        .unwrap_or(1)
+                });
+            let mut numbers = numbers.into_iter();
+            input.map_lines(|x| match numbers.next().flatten() {
+                Some(n) => format!("{:>w$}{}{}", n, separator, x, w = width),
+                None => format!("{:w$}{}{}", "", separator, x, w = width),
+            })
+        }
+        "elide" => {
+            let marker = match args.first() {
+                Some(x) => escaped(x),
+                None => elide_marker(context.language()).to_string(),
+            };
+            input.elide(&marker)
+        }
+        "details" => {
+            let options = Options::parse(args, &["open"]);
+            let open: bool = options.parse_or("open", false)?;
+            let open = match options.positional.get(1) {
+                Some(&"open") => true,
+                Some(x) => return Err(anyhow::anyhow!("Invalid 'details' argument: '{}'", x)),
+                None => open,
+            };
+            let summary = match options.positional.first() {
+                Some(x) => format!("<summary>{}</summary>\n", escaped(x)),
+                None => String::new(),
+            };
+            // GitHub only renders markdown inside HTML blocks when it is separated by blank lines
+            input.wrap(
+                &format!(
+                    "<details{}>\n{}\n",
+                    if open { " open" } else { "" },
+                    summary
+                ),
+                "\n\n</details>",
+            )
+        }
+        "quote" => render::quote(input),
+        "admonition" => {
+            let kind = args.first().context("Missing admonition kind")?;
+            let title = args.get(1).map(|x| escaped(x));
+            config
+                .renderer
+                .admonition(input, render::Admonition::from_arg(kind)?, title.as_deref())
+        }
+        "shift-headings" => {
+            let shift = args.first().context("Missing heading shift, e.g. +1")?;
+            let shift = shift
+                .parse::<isize>()
+                .with_context(|| format!("Invalid heading shift: '{}'", shift))?;
+            let (lines, indices) = markdown::shift_headings(&input.lines(), shift);
+            Snippet::new(
+                lines.join("\n"),
+                indices.iter().map(|i| input.origins[*i]).collect(),
+            )
+        }
+        "rewrite-links" => {
+            let source = context
+                .source
+                .context("'rewrite-links' needs the path of the included file")?;
+            let document = config
+                .document
+                .as_ref()
+                .context("'rewrite-links' needs the path of the document being written")?;
+            let source = match source.starts_with(git::GIT_PREFIX) {
+                true => git::parse_source(source)?.1,
+                false => source,
+            };
+            let source = config.base_dir.join(source);
+            let from = source.parent().unwrap_or_else(|| Path::new(""));
+            let to = document.parent().unwrap_or_else(|| Path::new(""));
+            let pattern = config.regex.get(markdown::LINK_PATTERN, None)?;
+            let lines = markdown::rewrite_links(&input.lines(), &pattern, |url| {
+                markdown::rewrite_url(url, from, to)
+            })?;
+            input.replace_text(lines.join("\n"))
+        }
+        "max-depth" => {
+            let depth = args.first().context("Missing 'max-depth' argument")?;
+            let depth = depth
+                .parse::<usize>()
+                .with_context(|| format!("Invalid depth: '{}'", depth))?;
+            input.select(&markdown::max_depth(&input.lines(), depth))
+        }
+        "date-format" => {
+            let format = args
+                .first()
+                .context("Missing date format, e.g. \"%Y-%m-%d\"")?;
+            Snippet::generated(date::format(text, &escaped(format))?)
         }
         "wrap" => {
-            let before = args.get(0).context("Missing 'before' wrap argument")?;
+            let before = args.first().context("Missing 'before' wrap argument")?;
             let after = args.get(1).unwrap_or(before);
-            format!("{}{}{}", escaped(before), input, escaped(after))
+            input.wrap(&escaped(before), &escaped(after))
         }
         "wrap-lines" => {
-            let before = args.get(0).context("Missing 'before' wrap argument")?;
+            let before = args.first().context("Missing 'before' wrap argument")?;
             let after = args.get(1).unwrap_or(before);
             let before = escaped(before);
             let after = escaped(after);
-            input
-                .lines()
-                .enumerate()
-                .flat_map(|(i, x)| match i {
-                    0 => vec![before.as_str(), x, after.as_str()],
-                    _ => vec!["\n", before.as_str(), x, after.as_str()],
-                })
-                .collect()
+            input.map_lines(|x| format!("{}{}{}", before, x, after))
         }
-        "match" => {
-            let re = args.get(0).context("Missing regex string given")?.as_ref();
-            let re = regex::Regex::new(re)?;
-            let group = args
+        "match" | "match-all" => {
+            let options = Options::parse(args, &["nth", "flags"]);
+            let re = options
+                .positional
+                .first()
+                .context("Missing regex string given")?;
+            let re = config.regex.get(re, options.get("flags"))?;
+            let group = options.positional.get(1).unwrap_or(&"0"); // Capture all if no group specified
+            let mut matches = re
+                .captures_iter(text)
+                .map(|m| pattern::group(&m, group).map(|x| input.slice(x.start(), x.end())));
+            if command == "match-all" {
+                let separator = escaped(options.positional.get(2).unwrap_or(&"\\n"));
+                let matches = matches.collect::<Result<Vec<_>>>()?;
+                if matches.is_empty() {
+                    return Err(anyhow::anyhow!("Could not find match"));
+                }
+                Snippet::join(&matches, &separator)
+            } else {
+                let nth: usize = options.parse_or("nth", 1)?;
+                let index = nth.checked_sub(1).context("'nth' must be at least 1")?;
+                matches.nth(index).with_context(|| match nth {
+                    1 => "Could not find match".to_string(),
+                    _ => format!("Could not find match {}", nth),
+                })??
+            }
+        }
+        "replace" => {
+            let options = Options::parse(args, &["count", "flags"]);
+            let pattern = options
+                .positional
+                .first()
+                .context("Missing regex pattern")?;
+            let replacement = options
+                .positional
                 .get(1)
-                .map(|&x| x.parse().context("Invalid group number"))
-                .unwrap_or(Ok(0))?; // Captrue all if no group specified
-            let m = re.captures(input).context("Could not find match")?;
-            let group = m
-                .get(group)
-                .with_context(|| format!("Only {} groups in match", m.len()))?;
-            input[group.start()..group.end()].to_string()
+                .context("Missing replacement text")?;
+            let pattern = config.regex.get(pattern, options.get("flags"))?;
+            let output = match options.get("count") {
+                Some(_) => match options.parse_or("count", 0)? {
+                    0 => return Err(anyhow::anyhow!("'count' must be at least 1")),
+                    count => pattern.replacen(text, count, *replacement),
+                },
+                None => pattern.replace_all(text, *replacement),
+            };
+            input.replace_text(output.to_string())
+        }
+        "json-get" => {
+            Snippet::generated(json::get(text, args.first().context("Missing JSON path")?)?)
+        }
+        "json-table" => Snippet::generated(json::table(text, args)?),
+        "ipynb-cell" => {
+            let options = Options::parse_strict(args, &["tag", "id", "show"], command)?;
+            let mut selectors = options
+                .positional
+                .iter()
+                .map(|x| {
+                    x.parse()
+                        .map(ipynb::Selector::Index)
+                        .with_context(|| format!("Invalid cell index: '{}'", x))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            for (key, value) in &options.named {
+                match *key {
+                    "tag" => selectors.push(ipynb::Selector::Tag(value)),
+                    "id" => selectors.push(ipynb::Selector::Id(value)),
+                    _ => {}
+                }
+            }
+            let show = match options.get("show") {
+                Some(x) => ipynb::Show::from_arg(x)?,
+                None => ipynb::Show::Source,
+            };
+            if selectors.is_empty() {
+                return Err(anyhow::anyhow!("Missing cell index, tag or id"));
+            }
+            Snippet::generated(ipynb::cells(text, &selectors, show)?)
         }
 
         // Todo:
-        // Structured data (Csv, Json...) - row & column sorting, filtering, into table
-        _ => input.to_string(), // No transforms
+        // Structured data (Csv...) - row & column sorting, filtering, into table
+        _ => input.clone(), // No transforms
     })
 }
 
+///
+/// Creates a snippet from the contents of a file, without leading and trailing blank lines
+///
+fn file_snippet(contents: &str) -> Snippet {
+    // Count the lines trimmed from the start, so line numbers match the file
+    let trimmed = contents.trim_start();
+    let first_line = contents[..contents.len() - trimmed.len()]
+        .matches('\n')
+        .count()
+        + 1;
+    Snippet::from_source(trimmed.trim_end(), first_line)
+}
+
+///
+/// Returns true if a source has glob syntax, so it may match several files.
+/// A file with a name like `notes[1].md` is still included as a file if it exists.
+///
+fn is_glob(source: &str) -> bool {
+    !source.starts_with('@')
+        && !source.starts_with(git::GIT_PREFIX)
+        && source.contains(['*', '?', '['])
+}
+
+///
+/// Joins a glob pattern to a directory, escaping any glob syntax in the directory name
+///
+pub(crate) fn glob_pattern(dir: &Path, pattern: &str) -> Result<String> {
+    let dir = dir.to_str().context("Invalid directory for glob pattern")?;
+    let pattern = Path::new(&glob::Pattern::escape(dir)).join(pattern);
+    Ok(pattern
+        .to_str()
+        .context("Invalid glob pattern")?
+        .to_string())
+}
+
 pub struct Parser {
     pub config: ParserConfig,
     pub content: String,
@@ -297,16 +795,27 @@ impl Parser {
         Self { config, content }
     }
 
-    fn command_blocks(&self) -> IResult<&str, Vec<CommandSec>> {
+    ///
+    /// Sets the path the output is written to, used to rewrite relative links
+    ///
+    pub fn with_path<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.config.document = Some(path.into());
+        self
+    }
+
+    fn command_blocks<'a>(&'a self, content: &'a str) -> IResult<&'a str, Vec<CommandSec<'a>>> {
         let cmd = next_command_block(&self.config.tags);
-        many0(cmd)(&self.content)
+        many0(cmd)(content)
     }
 
-    fn command_groups(&self) -> Result<Vec<(CommandSec, CommandSec)>> {
-        let (_, commands) = match self.command_blocks() {
+    fn command_groups<'a>(
+        &'a self,
+        content: &'a str,
+    ) -> Result<Vec<(CommandSec<'a>, CommandSec<'a>)>> {
+        let (_, commands) = match self.command_blocks(content) {
             Ok(x) => x,
             Err(err) => {
-                return Err(anyhow::anyhow!("Failed parsing: {}", format!("{}", err)));
+                return Err(anyhow::anyhow!("Failed parsing: {}", err));
             }
         };
 
@@ -330,7 +839,7 @@ impl Parser {
             .into_iter()
             .zip(end_blocks)
             .map(|(begin, end)| {
-                if begin.end(&self.content) >= end.start(&self.content) {
+                if begin.end(content) >= end.start(content) {
                     return Err(anyhow::anyhow!(
                         "Found extra end block before command: ({})",
                         begin
@@ -347,50 +856,385 @@ impl Parser {
     }
 
     ///
+    /// Reads a file relative to `base_dir`, or from a git revision (`git:revision:path`)
     ///
     /// # Parameters
-    /// * relative_filepath A filepath relative to `dir`
-    pub fn parse(&self) -> Result<String> {
-        let groups = self.command_groups()?;
-        let mut prev_end = 0;
-        let mut spans: Vec<Span> = vec![];
-        for (begin, end) in groups {
-            let filename = begin.commands.get(0).context("No filename")?.command;
-            let filename = self.config.base_dir.join(&filename);
-            let contents = std::fs::read_to_string(&filename).with_context(|| {
-                let before = begin.start(&self.content);
-                let line_num = self.content[0..before].lines().count();
-
-                format!(
-                    "On line {}: Could not read '{:?}'\n  while parsing: {:?}",
-                    line_num,
-                    &filename,
-                    &self.content[begin.start(&self.content)..begin.end(&self.content)],
-                )
-            })?;
-            spans.push(Span::Existing((prev_end, begin.end(&self.content))));
-            let mut output = contents.trim().to_string();
-            for cmd in begin.commands.iter().skip(1) {
-                output = transform(&output, &cmd)?;
-            }
-            spans.push(Span::Replace(output));
-            prev_end = end.start(&self.content);
+    /// * `content` The document containing the command block, used for error messages
+    /// * `begin` The command block
+    /// * `path` The file to read
+    ///
+    fn read_file(&self, content: &str, begin: &CommandSec, path: &str) -> Result<String> {
+        if path.starts_with(git::GIT_PREFIX) {
+            let (rev, path) = git::parse_source(path)?;
+            return git::show(&self.config.base_dir, rev, path);
         }
-        spans.push(Span::Existing((prev_end, self.content.len())));
+        let filename = self.config.base_dir.join(path);
+        std::fs::read_to_string(&filename).with_context(|| {
+            let before = begin.start(content);
+            let line_num = content[0..before].lines().count();
 
-        Ok(spans
+            format!(
+                "On line {}: Could not read '{:?}'\n  while parsing: {:?}",
+                line_num,
+                &filename,
+                &content[begin.start(content)..begin.end(content)],
+            )
+        })
+    }
+
+    ///
+    /// Creates a unified diff between two files, for the `@diff` source
+    ///
+    fn diff(&self, content: &str, begin: &CommandSec, args: &[&str]) -> Result<Snippet> {
+        let options = Options::parse(args, &["context", "lines", "start", "end", "flags"]);
+        let (old, new) = match options.positional.as_slice() {
+            [old, new] => (old, new),
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "'@diff' needs two files to compare, e.g. @diff \"v1.rs\" \"v2.rs\""
+                ))
+            }
+        };
+        let context: usize = options.parse_or("context", 3)?;
+        let flags = options.get("flags");
+        let start = options
+            .get("start")
+            .map(|x| self.config.regex.get(x, flags))
+            .transpose()?;
+        let end = options
+            .get("end")
+            .map(|x| self.config.regex.get(x, flags))
+            .transpose()?;
+        let old_text = self.read_file(content, begin, old)?;
+        let new_text = self.read_file(content, begin, new)?;
+        // Finds the lines to compare in each file
+        let region = |name: &str, lines: &[&str]| -> Result<Vec<usize>> {
+            let indices: Vec<usize> = match options.get("lines") {
+                Some(x) => lines::list(lines.len(), &[x])?,
+                None => (0..lines.len()).collect(),
+            };
+            let start = match &start {
+                Some(x) => x,
+                None => return Ok(indices),
+            };
+            let selected = indices.iter().map(|i| lines[*i]).collect::<Vec<_>>();
+            Ok(
+                lines::between(&selected, start, end.as_ref(), 1, lines::Include::Both)
+                    .with_context(|| format!("In '{}'", name))?
+                    .iter()
+                    .map(|i| indices[*i])
+                    .collect(),
+            )
+        };
+        let old_lines = old_text.lines().collect::<Vec<_>>();
+        let new_lines = new_text.lines().collect::<Vec<_>>();
+        Ok(Snippet::generated(diff::unified(
+            &diff::DiffInput::select(old, &old_lines, &region(old, &old_lines)?)?,
+            &diff::DiffInput::select(new, &new_lines, &region(new, &new_lines)?)?,
+            context,
+        )))
+    }
+
+    ///
+    /// Runs a command and includes its output, for the `@exec` source
+    ///
+    fn exec(&self, args: &[&str]) -> Result<Snippet> {
+        let options = Options::parse(args, &["output", "exit", "inputs"]);
+        let words = match options.positional.as_slice() {
+            [line] => exec::split_words(line)?,
+            words => words.iter().map(|x| x.to_string()).collect(),
+        };
+        let which = options.get("output").unwrap_or("stdout");
+        let capture = match which {
+            "both" => exec::Capture::Combined,
+            _ => exec::Capture::Separate,
+        };
+        let output = exec::run(&self.config.exec, &self.config.base_dir, &words, capture)?;
+        output.check_exit(&words, options.get("exit").unwrap_or("0"))?;
+        let text = output.text(which)?;
+        Ok(Snippet::generated(text.trim_end()))
+    }
+
+    ///
+    /// Runs a list of commands, for the `@session` source.
+    /// Creates a transcript of each command (after a prompt) followed by its output.
+    ///
+    fn session(&self, args: &[&str]) -> Result<Snippet> {
+        let options = Options::parse(
+            args,
+            &["exit", "prompt", "redact", "redact-paths", "inputs"],
+        );
+        let prompt = escaped(options.get("prompt").unwrap_or("$ "));
+        let redact = options
+            .get_all("redact")
             .iter()
-            .map(|x| -> &str {
-                match x {
-                    Span::Existing((begin, end)) => &self.content[*begin..*end],
-                    Span::Replace(text) => text.as_str(),
+            .map(|x| self.config.regex.get(x, None))
+            .collect::<Result<Vec<_>>>()?;
+        let mut paths = vec![];
+        if options.parse_or("redact-paths", false)? {
+            let dir = self
+                .config
+                .exec
+                .dir
+                .as_ref()
+                .unwrap_or(&self.config.base_dir);
+            // Commands see the canonical path, with symbolic links resolved
+            paths.push((std::fs::canonicalize(dir)?, "."));
+            if let Some(home) = std::env::var_os("HOME") {
+                let home = PathBuf::from(home);
+                paths.push((std::fs::canonicalize(&home).unwrap_or(home), "~"));
+            }
+        }
+        if options.positional.is_empty() {
+            return Err(anyhow::anyhow!(
+                "'@session' needs at least one command to run"
+            ));
+        }
+        let mut transcript = vec![];
+        for line in &options.positional {
+            let words = exec::split_words(line)?;
+            let output = exec::run(
+                &self.config.exec,
+                &self.config.base_dir,
+                &words,
+                exec::Capture::Combined,
+            )?;
+            output.check_exit(&words, options.get("exit").unwrap_or("0"))?;
+            let mut text = output.text("both")?;
+            // The working directory is replaced before HOME, as it is usually inside HOME
+            for (path, replacement) in &paths {
+                if let Some(path) = path.to_str().filter(|x| x.len() > 1) {
+                    text = text.replace(path, replacement);
                 }
+            }
+            for re in &redact {
+                text = re.replace_all(&text, "...").into_owned();
+            }
+            transcript.push(format!("{}{}", prompt, line));
+            transcript.extend(Some(text.trim_end().to_string()).filter(|x| !x.is_empty()));
+        }
+        Ok(code_block(
+            &Snippet::generated(transcript.join("\n")),
+            "console",
+        ))
+    }
+
+    ///
+    /// Reads the output of a source from the cache, or generates and caches it.
+    /// The cache key is the source text, the settings for running commands
+    /// and the contents of the files given with `inputs=`.
+    ///
+    fn cached<F: FnOnce() -> Result<Snippet>>(
+        &self,
+        source: &Command,
+        generate: F,
+    ) -> Result<Snippet> {
+        let dir = match &self.config.cache_dir {
+            Some(x) => x,
+            None => return generate(),
+        };
+        let inputs = Options::parse(&source.args, &["inputs"]).get_all("inputs");
+        let settings = self.config.exec.key_parts(&self.config.base_dir);
+        let mut parts = vec![source.command];
+        parts.extend(source.args.iter().copied());
+        parts.extend(settings.iter().map(|x| x.as_str()));
+        let key = cache::key(&parts, &self.config.base_dir, &inputs)?;
+        if !self.config.refresh_cache {
+            if let Some(text) = cache::get(dir, &key) {
+                return Ok(Snippet::generated(text));
+            }
+        }
+        let output = generate()?;
+        cache::set(dir, &key, &output.text)?;
+        Ok(output)
+    }
+
+    ///
+    /// Reads the input of a command block, from a file or a generated source (starting with `@`)
+    ///
+    /// # Parameters
+    /// * `content` The document containing the command block
+    /// * `begin` The command block
+    ///
+    fn read_source(&self, content: &str, begin: &CommandSec) -> Result<Snippet> {
+        let source = begin.commands.first().context("No filename")?;
+        match source.command {
+            "@diff" => return self.diff(content, begin, &source.args),
+            "@exec" => return self.cached(source, || self.exec(&source.args)),
+            "@session" => return self.cached(source, || self.session(&source.args)),
+            "@git-commit" => {
+                let options = Options::parse(&source.args, &["short"]);
+                let commit = git::commit(&self.config.base_dir, options.parse_or("short", false)?)?;
+                return Ok(Snippet::generated(commit));
+            }
+            "@git-tag" => return Ok(Snippet::generated(git::latest_tag(&self.config.base_dir)?)),
+            "@git-date" => {
+                let path = source
+                    .args
+                    .first()
+                    .context("'@git-date' needs the file to find the commit date of")?;
+                let timestamp = git::file_date(&self.config.base_dir, path)?;
+                return Ok(Snippet::generated(date::to_rfc3339(timestamp)?));
+            }
+            "@now" => return Ok(Snippet::generated(date::to_rfc3339(date::now()?)?)),
+            x if x.starts_with('@') => {
+                return Err(anyhow::anyhow!("Unknown source: '{}'", x));
+            }
+            _ => {}
+        }
+        let contents = self.read_file(content, begin, source.command)?;
+        Ok(file_snippet(&contents))
+    }
+
+    ///
+    /// Applies a list of commands to the input of a command block
+    ///
+    /// # Parameters
+    /// * `source` The included file, if the input was read from a file
+    /// * `input` The input of the command block
+    /// * `commands` The commands after the source
+    ///
+    fn pipeline(
+        &self,
+        source: Option<&str>,
+        input: Snippet,
+        commands: &[Command],
+    ) -> Result<Snippet> {
+        let context = TransformContext::new(&self.config, source);
+        commands.iter().try_fold(input, |output, cmd| {
+            transform_snippet(&context, &output, cmd)
+        })
+    }
+
+    ///
+    /// Includes every file matching a glob pattern, sorted by path.
+    /// The commands after the source are applied to each file,
+    /// then each file is inserted into a template and joined with a separator.
+    ///
+    fn expand_glob(&self, content: &str, begin: &CommandSec) -> Result<Snippet> {
+        let source = begin.commands.first().context("No filename")?;
+        let options = Options::parse(&source.args, &["template", "separator"]);
+        let template = escaped(options.get("template").unwrap_or("{content}"));
+        let separator = escaped(options.get("separator").unwrap_or("\\n\\n"));
+        if template.matches("{content}").count() != 1 {
+            return Err(anyhow::anyhow!(
+                "A glob template must contain {{content}} once: '{}'",
+                template
+            ));
+        }
+        let pattern = glob_pattern(&self.config.base_dir, source.command)?;
+        let mut files = glob::glob(&pattern)
+            .with_context(|| format!("Invalid glob pattern: '{}'", source.command))?
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        files.retain(|x| x.is_file());
+        files.sort();
+        if files.is_empty() {
+            return Err(anyhow::anyhow!("No files match '{}'", source.command));
+        }
+        let mut parts = vec![];
+        for file in files {
+            // Paths are relative to `base_dir`, like other included files
+            let path = file.strip_prefix(&self.config.base_dir).unwrap_or(&file);
+            let path = path.to_string_lossy().replace('\\', "/");
+            let contents = self.read_file(content, begin, &path)?;
+            let output =
+                self.pipeline(Some(&path), file_snippet(&contents), &begin.commands[1..])?;
+            let name = file.file_name().unwrap_or_default().to_string_lossy();
+            let stem = file.file_stem().unwrap_or_default().to_string_lossy();
+            let template = template
+                .replace("{path}", &path)
+                .replace("{name}", &name)
+                .replace("{stem}", &stem);
+            let (before, after) = template.split_once("{content}").unwrap_or_default();
+            parts.push(output.wrap(before, after));
+        }
+        Ok(Snippet::join(&parts, &separator))
+    }
+
+    ///
+    /// Creates the new contents of a command block
+    ///
+    fn expand_block(&self, content: &str, begin: &CommandSec) -> Result<String> {
+        let source = begin.commands.first().context("No filename")?.command;
+        let glob = is_glob(source) && !self.config.base_dir.join(source).is_file();
+        let output = match glob {
+            true => self.expand_glob(content, begin)?,
+            false => self.pipeline(
+                Some(source).filter(|x| !x.starts_with('@')),
+                self.read_source(content, begin)?,
+                &begin.commands[1..],
+            )?,
+        };
+        Ok(output.text)
+    }
+
+    ///
+    /// Replaces the contents of every command block.
+    /// Tables of contents are generated after the other blocks are replaced,
+    /// so they include headings from included files.
+    ///
+    pub fn parse(&self) -> Result<String> {
+        let content = &self.content;
+        let groups = self.command_groups(content)?;
+        let is_toc = |begin: &CommandSec| matches!(begin.commands.first(), Some(x) if x.command == TOC_SOURCE);
+        let mut outputs = groups
+            .iter()
+            .map(|(begin, _)| match is_toc(begin) {
+                true => Ok(None),
+                false => self.expand_block(content, begin).map(Some),
             })
-            .collect::<Vec<&str>>()
-            .join("\n"))
+            .collect::<Result<Vec<_>>>()?;
+        let expanded = replace_blocks(content, &groups, &outputs);
+        if !groups.iter().any(|(begin, _)| is_toc(begin)) {
+            return Ok(expanded);
+        }
+        let lines = expanded.lines().collect::<Vec<_>>();
+        let toc = Snippet::generated(markdown::toc(&markdown::headings(&lines)));
+        for ((begin, _), output) in groups.iter().zip(&mut outputs) {
+            if is_toc(begin) {
+                *output = Some(self.pipeline(None, toc.clone(), &begin.commands[1..])?.text);
+            }
+        }
+        Ok(replace_blocks(content, &groups, &outputs))
     }
 }
 
+///
+/// Replaces the contents of command blocks.
+///
+/// # Parameters
+/// * `content` The document containing the command blocks
+/// * `groups` The start and end of each command block
+/// * `outputs` The new contents of each block, or `None` to keep its current contents
+///
+fn replace_blocks(
+    content: &str,
+    groups: &[(CommandSec, CommandSec)],
+    outputs: &[Option<String>],
+) -> String {
+    let mut prev_end = 0;
+    let mut spans: Vec<Span> = vec![];
+    for ((begin, end), output) in groups.iter().zip(outputs) {
+        if let Some(output) = output {
+            spans.push(Span::Existing((prev_end, begin.end(content))));
+            spans.push(Span::Replace(output.clone()));
+            prev_end = end.start(content);
+        }
+    }
+    spans.push(Span::Existing((prev_end, content.len())));
+
+    spans
+        .iter()
+        .map(|x| -> &str {
+            match x {
+                Span::Existing((begin, end)) => &content[*begin..*end],
+                Span::Replace(text) => text.as_str(),
+            }
+        })
+        .collect::<Vec<&str>>()
+        .join("\n")
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -419,6 +1263,10 @@ mod test {
             Ok(("|", vec!["one", "two", "three 3"]))
         );
         assert_eq!(command_args(r#" one |"#), Ok(("|", vec!["one"])));
+        assert_eq!(
+            command_args(r#" one key=value |"#),
+            Ok(("|", vec!["one", "key=value"]))
+        );
         assert_eq!(command_args(r#" " one " |"#), Ok(("|", vec![" one "])));
         assert_eq!(
             command_args(r#" one "two  three"   | "#),
@@ -581,10 +1429,11 @@ mod test {
                 tags: CommandTags::new("(|", "|)"),
                 end_command: "end".to_string(),
                 base_dir: PathBuf::new(),
+                ..Default::default()
             },
             content: r#"A(|cmd|)X(|(|end|)Z"#.to_string(),
         };
-        let blocks = parser.command_blocks();
+        let blocks = parser.command_blocks(&parser.content);
         assert_eq!(
             blocks,
             Ok((
@@ -618,10 +1467,11 @@ mod test {
                 tags: CommandTags::new("(|", "|)"),
                 end_command: "end".to_string(),
                 base_dir: PathBuf::new(),
+                ..Default::default()
             },
             content: r#"A(|cmd|)X(|(|end|)Z"#.to_string(),
         };
-        let groups = parser.command_groups().unwrap();
+        let groups = parser.command_groups(&parser.content).unwrap();
         assert_eq!(
             groups,
             vec![(
@@ -0,0 +1,81 @@
+use anyhow::{Context, Result};
+use regex::{Captures, Match, Regex, RegexBuilder};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+///
+/// Compiled regexes, keyed by pattern and flags.
+/// Clones share the same cache, so a pattern is only compiled once across every block and file.
+///
+#[derive(Clone, Debug, Default)]
+pub(crate) struct RegexCache(Arc<Mutex<HashMap<(String, String), Regex>>>);
+
+impl RegexCache {
+    ///
+    /// Returns the compiled regex for `pattern` and `flags`, compiling it on first use.
+    ///
+    pub(crate) fn get(&self, pattern: &str, flags: Option<&str>) -> Result<Regex> {
+        let key = (pattern.to_string(), flags.unwrap_or_default().to_string());
+        let mut cache = self.0.lock().unwrap_or_else(|x| x.into_inner());
+        if let Some(re) = cache.get(&key) {
+            return Ok(re.clone());
+        }
+        let re = build(pattern, flags)?;
+        cache.insert(key, re.clone());
+        Ok(re)
+    }
+
+    #[cfg(test)]
+    pub(crate) fn len(&self) -> usize {
+        self.0.lock().unwrap_or_else(|x| x.into_inner()).len()
+    }
+}
+
+///
+/// Compiles a regex pattern with optional flags.
+///
+/// # Parameters
+/// * `pattern` A regex pattern
+/// * `flags` Letters that enable regex flags:
+///     * `i`: case-insensitive
+///     * `m`: multi-line (`^` and `$` match at the start and end of each line)
+///     * `s`: dot-all (`.` also matches newlines)
+///     * `x`: ignore whitespace and allow `#` comments in the pattern
+///
+fn build(pattern: &str, flags: Option<&str>) -> Result<Regex> {
+    let mut builder = RegexBuilder::new(pattern);
+    for flag in flags.unwrap_or_default().chars() {
+        match flag {
+            'i' => builder.case_insensitive(true),
+            'm' => builder.multi_line(true),
+            's' => builder.dot_matches_new_line(true),
+            'x' => builder.ignore_whitespace(true),
+            x => {
+                return Err(anyhow::anyhow!(
+                    "Unknown regex flag: '{}' (expected i, m, s or x)",
+                    x
+                ))
+            }
+        };
+    }
+    builder
+        .build()
+        .with_context(|| format!("Invalid regex: '{}'", pattern))
+}
+
+///
+/// Gets a capture group by number (`0` is the whole match) or by name
+///
+pub(crate) fn group<'t>(captures: &Captures<'t>, group: &str) -> Result<Match<'t>> {
+    match group.parse::<usize>() {
+        Ok(i) if i >= captures.len() => {
+            Err(anyhow::anyhow!("Only {} groups in match", captures.len()))
+        }
+        Ok(i) => captures
+            .get(i)
+            .with_context(|| format!("Group {} did not take part in the match", i)),
+        Err(_) => captures
+            .name(group)
+            .with_context(|| format!("No group named '{}' in match", group)),
+    }
+}
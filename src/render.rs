@@ -0,0 +1,252 @@
+use crate::snippet::Snippet;
+use anyhow::Result;
+
+///
+/// The markdown renderer that generated output is written for.
+/// Used for syntax that isn't part of standard markdown, such as code block titles.
+///
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Renderer {
+    #[default]
+    Github,
+    Docusaurus,
+    MkDocs,
+    MdBook,
+    /// Standard markdown without any extensions
+    Plain,
+}
+
+/// The kind of an admonition, using the names of GitHub alerts
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Admonition {
+    Note,
+    Tip,
+    Important,
+    Warning,
+    Caution,
+}
+
+impl Admonition {
+    pub(crate) fn from_arg(arg: &str) -> Result<Self> {
+        Ok(match arg.to_lowercase().as_str() {
+            "note" => Admonition::Note,
+            "tip" => Admonition::Tip,
+            "important" => Admonition::Important,
+            "warning" => Admonition::Warning,
+            "caution" => Admonition::Caution,
+            x => {
+                return Err(anyhow::anyhow!(
+                    "Invalid admonition: '{}' (expected note, tip, important, warning or caution)",
+                    x
+                ))
+            }
+        })
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Admonition::Note => "note",
+            Admonition::Tip => "tip",
+            Admonition::Important => "important",
+            Admonition::Warning => "warning",
+            Admonition::Caution => "caution",
+        }
+    }
+
+    /// The closest admonition supported by Docusaurus and MkDocs
+    fn common_name(self) -> &'static str {
+        match self {
+            Admonition::Important => "info",
+            Admonition::Caution => "danger",
+            x => x.name(),
+        }
+    }
+
+    fn title(self) -> String {
+        let name = self.name();
+        format!("{}{}", name[..1].to_uppercase(), &name[1..])
+    }
+}
+
+/// Adds quotes to the value of a `key=value` attribute
+fn quote_attribute(attr: &str) -> String {
+    match attr.find('=') {
+        Some(i) if !attr[i + 1..].starts_with('"') => {
+            format!("{}=\"{}\"", &attr[..i], &attr[i + 1..])
+        }
+        _ => attr.to_string(),
+    }
+}
+
+///
+/// Creates a code fence that is longer than any run of backticks inside `text`
+///
+pub(crate) fn fence(text: &str) -> String {
+    let longest = text.split(|x| x != '`').map(|x| x.len()).max().unwrap_or(0);
+    "`".repeat(longest.max(2) + 1)
+}
+
+///
+/// Prefixes each line with `> `. Blank lines are prefixed with `>` only.
+///
+pub(crate) fn quote(input: &Snippet) -> Snippet {
+    input.map_lines(|x| match x.is_empty() {
+        true => ">".to_string(),
+        false => format!("> {}", x),
+    })
+}
+
+///
+/// Checks a list of line ranges to highlight, e.g. `1,3-5`
+///
+pub(crate) fn parse_highlight(arg: &str) -> Result<Vec<&str>> {
+    arg.split(',')
+        .map(|range| {
+            let valid =
+                !range.is_empty() && range.splitn(2, '-').all(|x| x.parse::<usize>().is_ok());
+            match valid {
+                true => Ok(range),
+                false => Err(anyhow::anyhow!("Invalid highlight range: '{}'", range)),
+            }
+        })
+        .collect()
+}
+
+impl Renderer {
+    ///
+    /// Creates the info string that follows the opening fence of a code block.
+    ///
+    /// # Parameters
+    /// * `language` The language used for syntax highlighting (may be empty)
+    /// * `title` A title shown above the code block
+    /// * `highlight` Line ranges to highlight, e.g. `["1", "3-5"]`
+    /// * `attributes` Extra attributes, either `key=value` or a single word
+    ///
+    pub(crate) fn fence_info(
+        self,
+        language: &str,
+        title: Option<&str>,
+        highlight: &[&str],
+        attributes: &[&str],
+    ) -> Result<String> {
+        let mut info = vec![];
+        match self {
+            Renderer::Plain if title.is_some() || !highlight.is_empty() => {
+                return Err(anyhow::anyhow!(
+                    "Code block titles and highlighted lines are not supported by plain markdown"
+                ));
+            }
+            Renderer::Plain => {}
+            Renderer::MdBook => {
+                if title.is_some() || !highlight.is_empty() {
+                    return Err(anyhow::anyhow!(
+                        "Code block titles and highlighted lines are not supported by mdBook"
+                    ));
+                }
+                // mdBook attributes are separated by commas: ```rust,ignore
+                let attrs = std::iter::once(language)
+                    .filter(|x| !x.is_empty())
+                    .chain(attributes.iter().copied())
+                    .collect::<Vec<_>>();
+                return Ok(attrs.join(","));
+            }
+            // GitHub doesn't support titles or highlighted lines in the info string,
+            // so the title is written above the block instead (see `title_line`)
+            Renderer::Github => {}
+            Renderer::Docusaurus => {
+                info.extend(title.map(|x| format!("title=\"{}\"", x)));
+                if !highlight.is_empty() {
+                    info.push(format!("{{{}}}", highlight.join(",")));
+                }
+            }
+            Renderer::MkDocs => {
+                info.extend(title.map(|x| format!("title=\"{}\"", x)));
+                if !highlight.is_empty() {
+                    info.push(format!("hl_lines=\"{}\"", highlight.join(" ")));
+                }
+            }
+        }
+        info.extend(attributes.iter().map(|x| quote_attribute(x)));
+        Ok(std::iter::once(language.to_string())
+            .filter(|x| !x.is_empty())
+            .chain(info)
+            .collect::<Vec<_>>()
+            .join(" "))
+    }
+
+    /// Returns true if code blocks can have titles
+    pub(crate) fn supports_titles(self) -> bool {
+        !matches!(self, Renderer::MdBook | Renderer::Plain)
+    }
+
+    ///
+    /// The line written above a code block to show its title,
+    /// for renderers that can't include the title in the info string
+    ///
+    pub(crate) fn title_line(self, title: Option<&str>) -> Option<String> {
+        match self {
+            Renderer::Github => title.map(|x| format!("**{}**\n", x)),
+            _ => None,
+        }
+    }
+
+    ///
+    /// Wraps `input` in an admonition (also known as a callout or alert).
+    /// The plain markdown renderer uses a blockquote starting with the title in bold.
+    ///
+    /// # Parameters
+    /// * `kind` The kind of admonition, which sets its color and icon
+    /// * `title` A title to replace the default title (e.g. "Note")
+    ///
+    pub(crate) fn admonition(
+        self,
+        input: &Snippet,
+        kind: Admonition,
+        title: Option<&str>,
+    ) -> Snippet {
+        match self {
+            Renderer::Github => {
+                // GitHub alerts don't have titles, so add the title as the first line
+                let header = match title {
+                    Some(title) => {
+                        format!("> [!{}]\n> **{}**\n>\n", kind.name().to_uppercase(), title)
+                    }
+                    None => format!("> [!{}]\n", kind.name().to_uppercase()),
+                };
+                quote(input).wrap(&header, "")
+            }
+            Renderer::Docusaurus => {
+                let title = title.map(|x| format!("[{}]", x)).unwrap_or_default();
+                input.wrap(
+                    &format!(":::{}{}\n\n", kind.common_name(), title),
+                    "\n\n:::",
+                )
+            }
+            Renderer::MkDocs => {
+                let title = title.map(|x| format!(" \"{}\"", x)).unwrap_or_default();
+                input
+                    .map_lines(|x| match x.is_empty() {
+                        true => String::new(),
+                        false => format!("    {}", x),
+                    })
+                    .wrap(&format!("!!! {}{}\n\n", kind.common_name(), title), "")
+            }
+            // Uses the syntax of the mdbook-admonish preprocessor
+            Renderer::MdBook => {
+                let fence = fence(&input.text);
+                let title = title
+                    .map(|x| format!(" title=\"{}\"", x))
+                    .unwrap_or_default();
+                input.wrap(
+                    &format!("{}admonish {}{}\n", fence, kind.name(), title),
+                    &format!("\n{}", fence),
+                )
+            }
+            Renderer::Plain => {
+                let title = title.map(|x| x.to_string()).unwrap_or_else(|| kind.title());
+                quote(input).wrap(&format!("> **{}**\n>\n", title), "")
+            }
+        }
+    }
+}
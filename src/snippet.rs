@@ -0,0 +1,162 @@
+///
+/// Text passed between commands, along with the line number each line had in the included file.
+/// Commands that select or modify lines keep the original line numbers,
+/// so `line-numbers` can still show them after lines have been filtered.
+///
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Snippet {
+    pub text: String,
+    /// The 1-based line number of each line in the included file, or `None` for generated lines
+    pub origins: Vec<Option<usize>>,
+}
+
+impl Snippet {
+    ///
+    /// Creates a snippet, fitting `origins` to the number of lines in `text`.
+    ///
+    pub fn new<S: Into<String>>(text: S, mut origins: Vec<Option<usize>>) -> Self {
+        let text = text.into();
+        origins.resize(text.lines().count(), None);
+        Self { text, origins }
+    }
+
+    ///
+    /// Text read from an included file, where the first line is line `first_line` of the file
+    ///
+    pub fn from_source<S: Into<String>>(text: S, first_line: usize) -> Self {
+        let text = text.into();
+        let origins = (first_line..first_line + text.lines().count())
+            .map(Some)
+            .collect();
+        Self { text, origins }
+    }
+
+    ///
+    /// Text that was generated by a command, rather than read from a file
+    ///
+    pub fn generated<S: Into<String>>(text: S) -> Self {
+        Self::new(text, vec![])
+    }
+
+    pub fn lines(&self) -> Vec<&str> {
+        self.text.lines().collect()
+    }
+
+    ///
+    /// Keeps only the lines at `indices` (0-based), in the order given
+    ///
+    pub fn select(&self, indices: &[usize]) -> Self {
+        let lines = self.lines();
+        Self {
+            text: indices
+                .iter()
+                .map(|i| lines[*i])
+                .collect::<Vec<_>>()
+                .join("\n"),
+            origins: indices.iter().map(|i| self.origins[*i]).collect(),
+        }
+    }
+
+    ///
+    /// Transforms each line, keeping its line number
+    ///
+    pub fn map_lines<F: FnMut(&str) -> String>(&self, mut f: F) -> Self {
+        let mut origins = vec![];
+        let text = self
+            .text
+            .lines()
+            .zip(self.origins.iter().chain(std::iter::repeat(&None)))
+            .map(|(line, origin)| {
+                let line = f(line);
+                // A mapped line may span several lines, which all keep its origin
+                origins.extend(std::iter::repeat_n(*origin, line.matches('\n').count() + 1));
+                line
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        Self::new(text, origins)
+    }
+
+    ///
+    /// Replaces the text of the snippet.
+    /// Line numbers are kept only if the number of lines is unchanged.
+    ///
+    pub fn replace_text(&self, text: String) -> Self {
+        match text.lines().count() == self.origins.len() {
+            true => Self::new(text, self.origins.clone()),
+            false => Self::generated(text),
+        }
+    }
+
+    ///
+    /// Inserts text before and after the snippet.
+    /// Any lines added by `before` and `after` have no line number.
+    ///
+    pub fn wrap(&self, before: &str, after: &str) -> Self {
+        let added = before.matches('\n').count();
+        let mut origins = vec![None; added];
+        origins.extend(self.origins.iter().cloned());
+        Self::new(format!("{}{}{}", before, self.text, after), origins)
+    }
+
+    ///
+    /// Keeps the text between the byte offsets `start` and `end`
+    ///
+    pub fn slice(&self, start: usize, end: usize) -> Self {
+        let first = self.text[..start].matches('\n').count();
+        Self::new(
+            &self.text[start..end],
+            self.origins.iter().skip(first).cloned().collect(),
+        )
+    }
+
+    ///
+    /// Joins snippets with a separator, keeping the line numbers of each snippet
+    ///
+    pub fn join(parts: &[Snippet], separator: &str) -> Self {
+        let added = separator.matches('\n').count();
+        let mut origins: Vec<Option<usize>> = vec![];
+        for (i, part) in parts.iter().enumerate() {
+            let mut next = part.origins.iter().cloned();
+            if i > 0 {
+                match added {
+                    // The separator joins the last line with the first line of the next part
+                    0 => {
+                        next.next();
+                    }
+                    x => origins.extend(vec![None; x - 1]),
+                }
+            }
+            origins.extend(next);
+        }
+        let text = parts
+            .iter()
+            .map(|x| x.text.as_str())
+            .collect::<Vec<_>>()
+            .join(separator);
+        Self::new(text, origins)
+    }
+
+    ///
+    /// Inserts a line containing `marker` wherever consecutive lines are not consecutive in the included file.
+    /// The marker is indented to match the line that follows it.
+    ///
+    pub fn elide(&self, marker: &str) -> Self {
+        let mut lines = vec![];
+        let mut origins = vec![];
+        let mut previous: Option<usize> = None;
+        for (line, origin) in self.text.lines().zip(&self.origins) {
+            if let (Some(prev), Some(next)) = (previous, origin) {
+                if *next != prev + 1 {
+                    let indent = line.len() - line.trim_start().len();
+                    lines.push(format!("{}{}", &line[..indent], marker));
+                    origins.push(None);
+                }
+            }
+            lines.push(line.to_string());
+            origins.push(*origin);
+            previous = *origin;
+        }
+        Self::new(lines.join("\n"), origins)
+    }
+}
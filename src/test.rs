@@ -1,7 +1,9 @@
 use crate::config::{Config, ConfigAndPath};
-use crate::parse::{transform, Command, Parser, ParserConfig};
-use crate::{transform_files_with_args, Args};
-use std::path::Path;
+use crate::parse::{transform, transform_snippet, Command, Parser, ParserConfig, TransformContext};
+use crate::snippet::Snippet;
+use crate::{transform_files_with_args, Args, ExecConfig, Renderer};
+use anyhow::Result;
+use std::path::{Path, PathBuf};
 
 #[test]
 fn with_language() {
@@ -41,7 +43,7 @@ end"#;
     let expected = r#"
 start
 <!--{ code_snippet.rs | code }-->
-```
+```rust
 fn main() {
     println!("Hello World!");
 }
@@ -178,6 +180,7 @@ fn process_config_correct() {
             next_dirs: vec![],
             depend_dirs: vec![],
             out_dir: None,
+            ..Default::default()
         },
         path: Path::new("root").join(".md-inc.toml"),
     }
@@ -315,6 +318,20 @@ c"#;
     let parsed = transform(original, &cmd).unwrap();
     assert_eq!(parsed, expected);
 }
+
+#[test]
+fn cmd_wrap_lines_with_newlines() {
+    let wrap = Command::new("wrap-lines", vec!["\n", ""]);
+    let parsed = pipeline("one\ntwo", &[wrap.clone(), Command::new("grep", vec!["."])]);
+    assert_eq!(parsed, "one\ntwo");
+    let parsed = pipeline(
+        "one\ntwo",
+        &[wrap.clone(), Command::new("lines", vec!["1", "4"])],
+    );
+    assert_eq!(parsed, "\none\n\ntwo");
+    let parsed = pipeline("one\ntwo", &[wrap, Command::new("line-numbers", vec![])]);
+    assert_eq!(parsed, "1: \n1: one\n2: \n2: two");
+}
 #[test]
 fn cmd_line_numbers() {
     let original = r#"a
@@ -400,3 +417,1535 @@ fn other() {
     let parsed = transform(original, &cmd).unwrap();
     assert_eq!(parsed, expected);
 }
+
+#[test]
+fn cmd_json_table() {
+    let original = r#"[
+    {"name": "alpha", "size": 1, "meta": {"tag": "a|b", "ok": true}},
+    {"name": "beta", "size": 2, "extra": null},
+    {"name": "gamma", "meta": {"tag": "c"}}
+]"#;
+    let expected = r#"| name | size | meta.tag | meta.ok | extra |
+| --- | --- | --- | --- | --- |
+| alpha | 1 | a\|b | true |  |
+| beta | 2 |  |  |  |
+| gamma |  | c |  |  |"#;
+    let cmd = Command::new("json-table", vec![]);
+    let parsed = transform(original, &cmd).unwrap();
+    assert_eq!(parsed, expected);
+
+    let expected = r#"| meta.tag | name |
+| --- | --- |
+| a\|b | alpha |
+|  | beta |
+| c | gamma |"#;
+    let cmd = Command::new("json-table", vec!["meta.tag", "name"]);
+    let parsed = transform(original, &cmd).unwrap();
+    assert_eq!(parsed, expected);
+
+    let cmd = Command::new("json-table", vec!["missing"]);
+    assert!(transform(original, &cmd).is_err());
+    let cmd = Command::new("json-table", vec![]);
+    assert!(transform(r#"{"name": "alpha"}"#, &cmd).is_err());
+}
+
+#[test]
+fn cmd_json_get() {
+    let original = r#"{"data": {"version": "1.2.0", "items": [{"id": 1}, {"id": 2}]}}"#;
+    let cmd = Command::new("json-get", vec!["data.version"]);
+    assert_eq!(transform(original, &cmd).unwrap(), "1.2.0");
+    let cmd = Command::new("json-get", vec!["data.items.1"]);
+    assert_eq!(transform(original, &cmd).unwrap(), "{\n  \"id\": 2\n}");
+    let cmd = Command::new("json-get", vec!["data.missing"]);
+    assert!(transform(original, &cmd).is_err());
+
+    let get = Command::new("json-get", vec!["data.items"]);
+    let table = Command::new("json-table", vec![]);
+    let parsed = transform(transform(original, &get).unwrap(), &table).unwrap();
+    assert_eq!(parsed, "| id |\n| --- |\n| 1 |\n| 2 |");
+}
+
+#[test]
+fn cmd_ipynb_cell() {
+    let path = Path::new(&env!("CARGO_MANIFEST_DIR")).join("test_helpers/notebook.ipynb");
+    let original = std::fs::read_to_string(path).unwrap();
+    let cmd = Command::new("ipynb-cell", vec!["2"]);
+    let parsed = transform(&original, &cmd).unwrap();
+    assert_eq!(parsed, "import math\nradius = 2");
+
+    let cmd = Command::new("ipynb-cell", vec!["id=area", "show=outputs"]);
+    let parsed = transform(&original, &cmd).unwrap();
+    assert_eq!(parsed, "Calculating...\n12.566370614359172");
+
+    let expected = r#"import math
+radius = 2
+
+print("Calculating...")
+math.pi * radius ** 2
+
+Calculating...
+12.566370614359172"#;
+    let cmd = Command::new("ipynb-cell", vec!["tag=example", "show=both"]);
+    let parsed = transform(&original, &cmd).unwrap();
+    assert_eq!(parsed, expected);
+
+    let cmd = Command::new("ipynb-cell", vec!["tag=missing"]);
+    assert!(transform(&original, &cmd).is_err());
+    let cmd = Command::new("ipynb-cell", vec![]);
+    assert!(transform(&original, &cmd).is_err());
+    let cmd = Command::new("ipynb-cell", vec!["tags=example"]);
+    let err = transform(&original, &cmd).unwrap_err();
+    assert_eq!(err.to_string(), "Unknown 'ipynb-cell' option: 'tags'");
+}
+
+#[test]
+fn ipynb_cell_in_code_block() {
+    let original = r#"<!--{ notebook.ipynb | ipynb-cell: id=setup | code: python }-->
+<!--{ end }-->"#;
+    let expected = r#"<!--{ notebook.ipynb | ipynb-cell: id=setup | code: python }-->
+```python
+import math
+radius = 2
+```
+<!--{ end }-->"#;
+    let parsed = Parser {
+        config: ParserConfig {
+            base_dir: "test_helpers".into(),
+            ..Default::default()
+        },
+        content: original.to_string(),
+    }
+    .parse()
+    .unwrap();
+    assert_eq!(parsed, expected);
+}
+
+#[test]
+fn cmd_lines_from_end() {
+    let original = "1\n2\n3\n4\n5";
+    let cmd = Command::new("lines", vec!["-2"]);
+    assert_eq!(transform(original, &cmd).unwrap(), "4\n5");
+    let cmd = Command::new("lines", vec!["2", "-2"]);
+    assert_eq!(transform(original, &cmd).unwrap(), "2\n3\n4");
+    let cmd = Command::new("lines", vec!["-5", "1"]);
+    assert_eq!(transform(original, &cmd).unwrap(), "1");
+}
+
+#[test]
+fn cmd_lines_out_of_range() {
+    let original = "1\n2\n3";
+    for args in [
+        vec!["4"],
+        vec!["1", "4"],
+        vec!["-4"],
+        vec!["0"],
+        vec!["3", "2"],
+    ]
+    .iter()
+    {
+        let cmd = Command::new("lines", args.clone());
+        assert!(transform(original, &cmd).is_err(), "{:?}", args);
+    }
+    let cmd = Command::new("line", vec!["1-4"]);
+    let err = transform(original, &cmd).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Line 4 is out of range (input has 3 lines)"
+    );
+}
+
+#[test]
+fn cmd_line_ranges() {
+    let original = "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n11\n12";
+    let cmd = Command::new("line", vec!["1-3", "7", "10-12"]);
+    assert_eq!(transform(original, &cmd).unwrap(), "1\n2\n3\n7\n10\n11\n12");
+    let cmd = Command::new("line", vec!["-1", "3-1", "-3--2"]);
+    assert_eq!(transform(original, &cmd).unwrap(), "12\n3\n2\n1\n10\n11");
+}
+
+#[test]
+fn cmd_between() {
+    let original = r#"// Setup
+fn helper() {
+}
+// ANCHOR: example
+fn main() {
+    helper();
+}
+// ANCHOR_END: example
+// ANCHOR: example
+fn other() {
+}
+// ANCHOR_END: example"#;
+    let cmd = Command::new("between", vec!["^fn main", r#"^\}"#]);
+    assert_eq!(
+        transform(original, &cmd).unwrap(),
+        "fn main() {\n    helper();\n}"
+    );
+    let cmd = Command::new(
+        "between",
+        vec!["ANCHOR: example", "ANCHOR_END", "include=none"],
+    );
+    assert_eq!(
+        transform(original, &cmd).unwrap(),
+        "fn main() {\n    helper();\n}"
+    );
+    let cmd = Command::new(
+        "between",
+        vec!["ANCHOR: example", "ANCHOR_END", "nth=2", "include=start"],
+    );
+    assert_eq!(
+        transform(original, &cmd).unwrap(),
+        "// ANCHOR: example\nfn other() {\n}"
+    );
+    let cmd = Command::new("between", vec!["^fn other", "include=none"]);
+    assert_eq!(
+        transform(original, &cmd).unwrap(),
+        "}\n// ANCHOR_END: example"
+    );
+
+    let cmd = Command::new("between", vec!["ANCHOR: example", "ANCHOR_END", "nth=3"]);
+    assert!(transform(original, &cmd).is_err());
+    let cmd = Command::new("between", vec!["^fn other", "missing"]);
+    assert!(transform(original, &cmd).is_err());
+}
+
+#[test]
+fn cmd_grep() {
+    let original = r#"use std::fs;
+// TODO: remove
+fn main() {
+    log::debug!("start");
+    let x = 1;
+    log::debug!("x = {}", x);
+    println!("{}", x);
+}"#;
+    let cmd = Command::new("grep", vec!["log::"]);
+    assert_eq!(
+        transform(original, &cmd).unwrap(),
+        "    log::debug!(\"start\");\n    log::debug!(\"x = {}\", x);"
+    );
+    let cmd = Command::new("grep", vec!["^fn", "after=1"]);
+    assert_eq!(
+        transform(original, &cmd).unwrap(),
+        "fn main() {\n    log::debug!(\"start\");"
+    );
+    let cmd = Command::new("grep", vec!["let x", "context=1"]);
+    assert_eq!(
+        transform(original, &cmd).unwrap(),
+        "    log::debug!(\"start\");\n    let x = 1;\n    log::debug!(\"x = {}\", x);"
+    );
+    let cmd = Command::new("grep", vec!["missing"]);
+    assert_eq!(transform(original, &cmd).unwrap(), "");
+}
+
+#[test]
+fn cmd_grep_v() {
+    let original = r#"fn add(a: i32, b: i32) -> i32 {
+    // TODO: overflow
+    a + b
+}
+#[cfg(test)]
+mod test {}"#;
+    let cmd = Command::new("grep-v", vec!["// TODO"]);
+    assert_eq!(
+        transform(original, &cmd).unwrap(),
+        "fn add(a: i32, b: i32) -> i32 {\n    a + b\n}\n#[cfg(test)]\nmod test {}"
+    );
+    let cmd = Command::new("grep-v", vec![r#"#\[cfg\(test\)\]"#, "after=1"]);
+    assert_eq!(
+        transform(original, &cmd).unwrap(),
+        "fn add(a: i32, b: i32) -> i32 {\n    // TODO: overflow\n    a + b\n}"
+    );
+}
+
+#[test]
+fn cmd_replace() {
+    let original = r#"use internal_core::Client;
+let path = "/home/alice/project/data.csv";
+let client = internal_core::Client::new();"#;
+    let cmd = Command::new("replace", vec!["internal_core", "md_inc"]);
+    assert_eq!(
+        transform(original, &cmd).unwrap(),
+        r#"use md_inc::Client;
+let path = "/home/alice/project/data.csv";
+let client = md_inc::Client::new();"#
+    );
+    let cmd = Command::new("replace", vec!["internal_core", "md_inc", "count=1"]);
+    assert_eq!(
+        transform(original, &cmd).unwrap(),
+        r#"use md_inc::Client;
+let path = "/home/alice/project/data.csv";
+let client = internal_core::Client::new();"#
+    );
+    let cmd = Command::new("replace", vec!["internal_core", "md_inc", "count=0"]);
+    let err = transform(original, &cmd).unwrap_err();
+    assert_eq!(err.to_string(), "'count' must be at least 1");
+    let cmd = Command::new("replace", vec![r#"/home/(?P<user>\w+)/"#, "/users/$user/"]);
+    assert_eq!(
+        transform(original, &cmd).unwrap(),
+        r#"use internal_core::Client;
+let path = "/users/alice/project/data.csv";
+let client = internal_core::Client::new();"#
+    );
+    let cmd = Command::new("replace", vec!["^LET (\\w+)", "const $1", "flags=im"]);
+    assert_eq!(
+        transform(original, &cmd).unwrap(),
+        r#"use internal_core::Client;
+const path = "/home/alice/project/data.csv";
+const client = internal_core::Client::new();"#
+    );
+    let cmd = Command::new("replace", vec!["a", "b", "flags=q"]);
+    assert!(transform(original, &cmd).is_err());
+}
+
+#[test]
+fn cmd_dedent() {
+    let original = "        fn new() -> Self {\n\n            Self {}\n        }";
+    let cmd = Command::new("dedent", vec![]);
+    assert_eq!(
+        transform(original, &cmd).unwrap(),
+        "fn new() -> Self {\n\n    Self {}\n}"
+    );
+
+    let original = "\tif x {\n\t\ty();\n  \t}";
+    assert_eq!(transform(original, &cmd).unwrap(), "if x {\n\ty();\n}");
+    let cmd = Command::new("dedent", vec!["tab-width=8"]);
+    let original = "\tif x {\n    \t  y();\n\t}";
+    assert_eq!(transform(original, &cmd).unwrap(), "if x {\n  y();\n}");
+
+    let cmd = Command::new("dedent", vec![]);
+    assert_eq!(transform("    a\n  b", &cmd).unwrap(), "  a\nb");
+}
+
+#[test]
+fn cmd_indent() {
+    let original = "a\n\n  b";
+    let cmd = Command::new("indent", vec![]);
+    assert_eq!(transform(original, &cmd).unwrap(), "    a\n\n      b");
+    let cmd = Command::new("indent", vec!["2"]);
+    assert_eq!(transform(original, &cmd).unwrap(), "  a\n\n    b");
+    let cmd = Command::new("indent", vec!["// "]);
+    assert_eq!(transform(original, &cmd).unwrap(), "// a\n\n//   b");
+}
+
+/// Applies each command in turn, like a pipeline in a command block
+fn pipeline(input: &str, commands: &[Command]) -> String {
+    let config = ParserConfig::default();
+    commands
+        .iter()
+        .try_fold(Snippet::from_source(input, 1), |x, cmd| {
+            transform_snippet(&TransformContext::new(&config, None), &x, cmd)
+        })
+        .unwrap()
+        .text
+}
+
+#[test]
+fn cmd_line_numbers_keep_source_lines() {
+    let original = "a\nb\nc\nd\ne\nf\ng\nh\ni\nj\nk\nl";
+    let parsed = pipeline(
+        original,
+        &[
+            Command::new("lines", vec!["8", "11"]),
+            Command::new("line-numbers", vec![]),
+        ],
+    );
+    assert_eq!(parsed, " 8: h\n 9: i\n10: j\n11: k");
+
+    let parsed = pipeline(
+        original,
+        &[
+            Command::new("grep", vec!["[aeiou]"]),
+            Command::new("dedent", vec![]),
+            Command::new("line-numbers", vec![") "]),
+        ],
+    );
+    assert_eq!(parsed, "1) a\n5) e\n9) i");
+
+    let parsed = pipeline(
+        original,
+        &[
+            Command::new("line", vec!["-1", "2"]),
+            Command::new("line-numbers", vec![]),
+        ],
+    );
+    assert_eq!(parsed, "12: l\n 2: b");
+
+    let parsed = pipeline(
+        "fn main() {\n    run();\n}\nfn run() {}",
+        &[
+            Command::new("match", vec![r#"(?s)fn run\(\) \{\}"#]),
+            Command::new("line-numbers", vec![]),
+        ],
+    );
+    assert_eq!(parsed, "4: fn run() {}");
+}
+
+#[test]
+fn cmd_line_numbers_start() {
+    let original = "a\nb\nc\nd";
+    let parsed = pipeline(
+        original,
+        &[
+            Command::new("lines", vec!["2", "3"]),
+            Command::new("line-numbers", vec!["start=100"]),
+        ],
+    );
+    assert_eq!(parsed, "100: b\n101: c");
+
+    let parsed = pipeline(
+        original,
+        &[
+            Command::new("lines", vec!["3"]),
+            Command::new("code", vec![]),
+            Command::new("line-numbers", vec![]),
+        ],
+    );
+    assert_eq!(parsed, " : ```\n3: c\n4: d\n : ```");
+}
+
+#[test]
+fn line_numbers_skip_trimmed_lines() {
+    let original = r#"<!--{ short_4.txt | lines: 2 | line-numbers }-->
+<!--{ end }-->"#;
+    let expected = r#"<!--{ short_4.txt | lines: 2 | line-numbers }-->
+4: second
+5: third
+<!--{ end }-->"#;
+    let parsed = Parser {
+        config: ParserConfig {
+            base_dir: "test_helpers".into(),
+            ..Default::default()
+        },
+        content: original.to_string(),
+    }
+    .parse()
+    .unwrap();
+    assert_eq!(parsed, expected);
+}
+
+#[test]
+fn cmd_match_nth_and_named() {
+    let original = r#"fn one() -> u32 { 1 }
+fn two() -> u32 { 2 }
+fn three() -> u32 { 3 }"#;
+    let cmd = Command::new("match", vec![r#"fn (\w+)"#, "1", "nth=2"]);
+    assert_eq!(transform(original, &cmd).unwrap(), "two");
+    let cmd = Command::new("match", vec![r#"fn (?P<name>\w+)"#, "name", "nth=3"]);
+    assert_eq!(transform(original, &cmd).unwrap(), "three");
+    let cmd = Command::new("match", vec![r#"FN (?P<name>\w+)"#, "name", "flags=i"]);
+    assert_eq!(transform(original, &cmd).unwrap(), "one");
+    let cmd = Command::new("match", vec![r#"^fn t.*$"#, "flags=m"]);
+    assert_eq!(transform(original, &cmd).unwrap(), "fn two() -> u32 { 2 }");
+
+    let cmd = Command::new("match", vec![r#"fn (\w+)"#, "1", "nth=4"]);
+    assert!(transform(original, &cmd).is_err());
+    let cmd = Command::new("match", vec![r#"fn (\w+)"#, "other"]);
+    assert!(transform(original, &cmd).is_err());
+}
+
+#[test]
+fn cmd_match_all() {
+    let original = r#"fn one() -> u32 { 1 }
+fn two() -> u32 { 2 }
+fn three() -> u32 { 3 }"#;
+    let cmd = Command::new("match-all", vec![r#"fn (\w+)\(\)"#]);
+    assert_eq!(
+        transform(original, &cmd).unwrap(),
+        "fn one()\nfn two()\nfn three()"
+    );
+    let cmd = Command::new("match-all", vec![r#"fn (?P<name>\w+)"#, "name", ", "]);
+    assert_eq!(transform(original, &cmd).unwrap(), "one, two, three");
+    let cmd = Command::new("match-all", vec!["missing"]);
+    assert!(transform(original, &cmd).is_err());
+
+    let parsed = pipeline(
+        original,
+        &[
+            Command::new("match-all", vec![r#"fn t\w+"#, "0", "\\n\\n"]),
+            Command::new("line-numbers", vec![]),
+        ],
+    );
+    assert_eq!(parsed, "2: fn two\n : \n3: fn three");
+}
+
+#[test]
+fn regex_cache_shared_between_blocks_and_files() {
+    let original = r#"<!--{ code_snippet.rs | match: "println!\(.*\)" }-->
+<!--{ end }-->
+<!--{ code_snippet.rs | grep: "println" | match: "println!\(.*\)" }-->
+<!--{ end }-->"#;
+    let config = ParserConfig {
+        base_dir: "test_helpers".into(),
+        ..Default::default()
+    };
+    for _ in 0..2 {
+        let parsed = Parser::new(config.clone(), original.to_string())
+            .parse()
+            .unwrap();
+        assert_eq!(parsed.matches(r#"println!("Hello World!")"#).count(), 2);
+    }
+    // One entry for each distinct pattern
+    assert_eq!(config.regex.len(), 2);
+}
+
+#[test]
+fn code_language_from_config() {
+    let original = r#"<!--{ include_dir/include_me.txt | code }-->
+<!--{ end }-->
+<!--{ code_snippet.rs | code }-->
+<!--{ end }-->"#;
+    let expected = r#"<!--{ include_dir/include_me.txt | code }-->
+```
+INCLUDED_CONTENT
+```
+<!--{ end }-->
+<!--{ code_snippet.rs | code }-->
+```rs
+fn main() {
+    println!("Hello World!");
+}
+```
+<!--{ end }-->"#;
+    let mut config = ParserConfig {
+        base_dir: "test_helpers".into(),
+        ..Default::default()
+    };
+    config.languages.insert("rs".to_string(), "rs".to_string());
+    let parsed = Parser::new(config, original.to_string()).parse().unwrap();
+    assert_eq!(parsed, expected);
+}
+
+#[test]
+fn code_fence_longer_than_content() {
+    let original = "Example:\n```rust\nfn main() {}\n```";
+    let expected = "````markdown\nExample:\n```rust\nfn main() {}\n```\n````";
+    let cmd = Command::new("code", vec!["markdown"]);
+    assert_eq!(transform(original, &cmd).unwrap(), expected);
+
+    let original = "Use `````` for long fences";
+    let expected = "```````\nUse `````` for long fences\n```````";
+    let cmd = Command::new("code", vec![]);
+    assert_eq!(transform(original, &cmd).unwrap(), expected);
+}
+
+#[test]
+fn config_languages() {
+    let config: Config = toml::from_str(
+        r#"
+[languages]
+rs = "rust,ignore"
+txt = "text"
+"#,
+    )
+    .unwrap();
+    let (parser, _files) = ConfigAndPath {
+        config,
+        path: Path::new("root").join(".md-inc.toml"),
+    }
+    .into_parser()
+    .unwrap();
+    assert_eq!(parser.languages["rs"], "rust,ignore");
+    assert_eq!(parser.languages["txt"], "text");
+    assert_eq!(parser.languages["py"], "python");
+}
+
+/// Applies a command using a custom config
+fn transform_with(config: &ParserConfig, input: &str, command: &Command) -> Result<String> {
+    let input = Snippet::from_source(input, 1);
+    Ok(transform_snippet(&TransformContext::new(config, None), &input, command)?.text)
+}
+
+#[test]
+fn code_attributes() {
+    let original = "fn main() {}";
+    let cmd = Command::new(
+        "code",
+        vec![
+            "rust",
+            "title=src/main.rs",
+            "highlight=1,3-5",
+            "showLineNumbers",
+        ],
+    );
+    assert_eq!(
+        transform(original, &cmd).unwrap(),
+        "**src/main.rs**\n```rust showLineNumbers\nfn main() {}\n```"
+    );
+
+    let config = ParserConfig {
+        renderer: Renderer::Docusaurus,
+        ..Default::default()
+    };
+    assert_eq!(
+        transform_with(&config, original, &cmd).unwrap(),
+        "```rust title=\"src/main.rs\" {1,3-5} showLineNumbers\nfn main() {}\n```"
+    );
+
+    let config = ParserConfig {
+        renderer: Renderer::MkDocs,
+        ..Default::default()
+    };
+    let cmd = Command::new("code", vec!["rust", "highlight=1,3-5", "linenums=1"]);
+    assert_eq!(
+        transform_with(&config, original, &cmd).unwrap(),
+        "```rust hl_lines=\"1 3-5\" linenums=\"1\"\nfn main() {}\n```"
+    );
+
+    let config = ParserConfig {
+        renderer: Renderer::MdBook,
+        ..Default::default()
+    };
+    let cmd = Command::new("code", vec!["rust", "ignore", "edition2018"]);
+    assert_eq!(
+        transform_with(&config, original, &cmd).unwrap(),
+        "```rust,ignore,edition2018\nfn main() {}\n```"
+    );
+    let cmd = Command::new("code", vec!["rust", "title=main.rs"]);
+    assert!(transform_with(&config, original, &cmd).is_err());
+
+    let cmd = Command::new("code", vec!["rust", "highlight=1-x"]);
+    assert!(transform(original, &cmd).is_err());
+
+    // A single-word attribute needs the language to be given with `lang=`
+    let cmd = Command::new("code", vec!["lang=rust", "showLineNumbers"]);
+    assert_eq!(
+        transform(original, &cmd).unwrap(),
+        "```rust showLineNumbers\nfn main() {}\n```"
+    );
+    // A `key=value` attribute isn't used as the language
+    let config = ParserConfig::default();
+    let context = TransformContext::new(&config, Some("main.rs"));
+    let cmd = Command::new("code", vec!["linenums=1"]);
+    let input = Snippet::from_source(original, 1);
+    assert_eq!(
+        transform_snippet(&context, &input, &cmd).unwrap().text,
+        "```rust linenums=\"1\"\nfn main() {}\n```"
+    );
+}
+
+#[test]
+fn code_title_from_source() {
+    let original = r#"<!--{ code_snippet.rs | code }-->
+<!--{ end }-->
+<!--{ code_snippet.rs | code: title=Example }-->
+<!--{ end }-->
+<!--{ code_snippet.rs | code: title= }-->
+<!--{ end }-->"#;
+    let config = ParserConfig {
+        base_dir: "test_helpers".into(),
+        code_title: true,
+        renderer: Renderer::Docusaurus,
+        ..Default::default()
+    };
+    let parsed = Parser::new(config.clone(), original.to_string())
+        .parse()
+        .unwrap();
+    let fences = parsed
+        .lines()
+        .filter(|x| x.starts_with("```rust"))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        fences,
+        vec![
+            "```rust title=\"code_snippet.rs\"",
+            "```rust title=\"Example\"",
+            "```rust"
+        ]
+    );
+    // Renderers without titles ignore `code_title`, but not an explicit title
+    let config = ParserConfig {
+        renderer: Renderer::MdBook,
+        ..config
+    };
+    let original = "<!--{ code_snippet.rs | code }-->\n<!--{ end }-->";
+    let parsed = Parser::new(config.clone(), original.to_string())
+        .parse()
+        .unwrap();
+    assert!(parsed.contains("\n```rust\n"));
+    let original = "<!--{ code_snippet.rs | code: title=Example }-->\n<!--{ end }-->";
+    assert!(Parser::new(config, original.to_string()).parse().is_err());
+}
+
+#[test]
+fn cmd_hide_lines() {
+    let original = r#"# use std::collections::HashMap;
+# fn main() {
+let mut map = HashMap::new();
+    # map.insert(1, 2);
+## not hidden
+#
+#[derive(Debug)]
+# }"#;
+    let cmd = Command::new("hide-lines", vec![]);
+    assert_eq!(
+        transform(original, &cmd).unwrap(),
+        "let mut map = HashMap::new();\n# not hidden\n#[derive(Debug)]"
+    );
+    let cmd = Command::new("unhide", vec![]);
+    assert_eq!(
+        transform(original, &cmd).unwrap(),
+        r#"use std::collections::HashMap;
+fn main() {
+let mut map = HashMap::new();
+    map.insert(1, 2);
+# not hidden
+
+#[derive(Debug)]
+}"#
+    );
+
+    let original = "setup() #hidden\n#! hidden\nshown()\n  #! also hidden\n## kept";
+    let cmd = Command::new("hide-lines", vec!["#! "]);
+    assert_eq!(
+        transform(original, &cmd).unwrap(),
+        "setup() #hidden\nshown()\n## kept"
+    );
+    let cmd = Command::new("unhide", vec!["#! "]);
+    assert_eq!(
+        transform(original, &cmd).unwrap(),
+        "setup() #hidden\nhidden\nshown()\n  also hidden\n## kept"
+    );
+}
+
+#[test]
+fn cmd_elide() {
+    let original = "fn main() {\n    let a = 1;\n    let b = 2;\n    let c = 3;\n}";
+    let output = pipeline(
+        original,
+        &[
+            Command::new("line", vec!["1", "2", "4", "5"]),
+            Command::new("elide", vec![]),
+        ],
+    );
+    assert_eq!(
+        output,
+        "fn main() {\n    let a = 1;\n    ...\n    let c = 3;\n}"
+    );
+
+    let output = pipeline(
+        original,
+        &[
+            Command::new("grep", vec!["let (a|c)"]),
+            Command::new("elide", vec!["/* snip */"]),
+        ],
+    );
+    assert_eq!(output, "    let a = 1;\n    /* snip */\n    let c = 3;");
+
+    // Contiguous lines are unchanged
+    let output = pipeline(
+        original,
+        &[
+            Command::new("lines", vec!["2", "3"]),
+            Command::new("elide", vec![]),
+        ],
+    );
+    assert_eq!(output, "    let a = 1;\n    let b = 2;");
+}
+
+#[test]
+fn elide_language_marker() {
+    let original = r#"<!--{ code_snippet.rs | line: 1 3 | elide }-->
+<!--{ end }-->"#;
+    let expected = r#"<!--{ code_snippet.rs | line: 1 3 | elide }-->
+fn main() {
+// ...
+}
+<!--{ end }-->"#;
+    let parsed = Parser {
+        config: ParserConfig {
+            base_dir: "test_helpers".into(),
+            ..Default::default()
+        },
+        content: original.to_string(),
+    }
+    .parse()
+    .unwrap();
+    assert_eq!(parsed, expected);
+}
+
+#[test]
+fn cmd_details() {
+    let original = "line 1\nline 2";
+    let cmd = Command::new("details", vec!["Output"]);
+    assert_eq!(
+        transform(original, &cmd).unwrap(),
+        "<details>\n<summary>Output</summary>\n\nline 1\nline 2\n\n</details>"
+    );
+    let cmd = Command::new("details", vec!["Output", "open"]);
+    assert_eq!(
+        transform(original, &cmd).unwrap(),
+        "<details open>\n<summary>Output</summary>\n\nline 1\nline 2\n\n</details>"
+    );
+    let cmd = Command::new("details", vec!["open=true"]);
+    assert_eq!(
+        transform(original, &cmd).unwrap(),
+        "<details open>\n\nline 1\nline 2\n\n</details>"
+    );
+    let cmd = Command::new("details", vec!["Output", "closed"]);
+    assert!(transform(original, &cmd).is_err());
+
+    // Line numbers are kept for the wrapped lines
+    let output = pipeline(
+        original,
+        &[
+            Command::new("details", vec!["Output"]),
+            Command::new("line-numbers", vec![]),
+        ],
+    );
+    assert_eq!(
+        output,
+        " : <details>\n : <summary>Output</summary>\n : \n1: line 1\n2: line 2\n : \n : </details>"
+    );
+}
+
+#[test]
+fn cmd_quote() {
+    let cmd = Command::new("quote", vec![]);
+    assert_eq!(
+        transform("First\n\nSecond", &cmd).unwrap(),
+        "> First\n>\n> Second"
+    );
+}
+
+#[test]
+fn cmd_admonition() {
+    let original = "Be careful\n\nwith this";
+    let with_renderer = |renderer: Renderer, args: Vec<&str>| {
+        let config = ParserConfig {
+            renderer,
+            ..Default::default()
+        };
+        transform_with(&config, original, &Command::new("admonition", args))
+    };
+    assert_eq!(
+        with_renderer(Renderer::Github, vec!["warning"]).unwrap(),
+        "> [!WARNING]\n> Be careful\n>\n> with this"
+    );
+    assert_eq!(
+        with_renderer(Renderer::Github, vec!["note", "Title"]).unwrap(),
+        "> [!NOTE]\n> **Title**\n>\n> Be careful\n>\n> with this"
+    );
+    assert_eq!(
+        with_renderer(Renderer::Docusaurus, vec!["caution", "Title"]).unwrap(),
+        ":::danger[Title]\n\nBe careful\n\nwith this\n\n:::"
+    );
+    assert_eq!(
+        with_renderer(Renderer::MkDocs, vec!["tip", "Title"]).unwrap(),
+        "!!! tip \"Title\"\n\n    Be careful\n\n    with this"
+    );
+    assert_eq!(
+        with_renderer(Renderer::MdBook, vec!["Important"]).unwrap(),
+        "```admonish important\nBe careful\n\nwith this\n```"
+    );
+    assert_eq!(
+        with_renderer(Renderer::Plain, vec!["note"]).unwrap(),
+        "> **Note**\n>\n> Be careful\n>\n> with this"
+    );
+    assert!(with_renderer(Renderer::Github, vec!["danger"]).is_err());
+    assert!(with_renderer(Renderer::Github, vec![]).is_err());
+}
+
+#[test]
+fn cmd_shift_headings() {
+    let original =
+        "# Title\n\nText\n\n---\nSubtitle\n--------\n## Section\n#hashtag\n###### Deepest";
+    let cmd = Command::new("shift-headings", vec!["+2"]);
+    assert_eq!(
+        transform(original, &cmd).unwrap(),
+        "### Title\n\nText\n\n---\n#### Subtitle\n#### Section\n#hashtag\n###### Deepest"
+    );
+    let cmd = Command::new("shift-headings", vec!["-1"]);
+    assert_eq!(
+        transform(original, &cmd).unwrap(),
+        "# Title\n\nText\n\n---\n# Subtitle\n# Section\n#hashtag\n##### Deepest"
+    );
+    let cmd = Command::new("shift-headings", vec!["two"]);
+    assert!(transform(original, &cmd).is_err());
+}
+
+#[test]
+fn include_markdown() {
+    let original = r#"<!--{ "guide/setup.md" | shift-headings: +1 | rewrite-links }-->
+<!--{ end }-->"#;
+    let expected = r#"<!--{ "guide/setup.md" | shift-headings: +1 | rewrite-links }-->
+## Setup
+
+See the [install notes](test_helpers/guide/install.md#linux) and the [example](test_helpers/code_snippet.rs).
+
+![Diagram](test_helpers/guide/images/diagram.png "Overview")
+
+### Configuration
+
+```bash
+# Not a heading: ./setup.md
+cat [link](setup.md)
+```
+
+[reference]: test_helpers/guide/reference.md
+<img src="test_helpers/guide/images/logo.png" alt="Logo">
+Links to [the web](https://example.com), [a section](#configuration) and [the root](/index.md).
+<!--{ end }-->"#;
+    let config = ParserConfig {
+        base_dir: "test_helpers".into(),
+        ..Default::default()
+    };
+    let parser = Parser::new(config.clone(), original.to_string()).with_path("README.md");
+    assert_eq!(parser.parse().unwrap(), expected);
+
+    // Links are relative to the document being written
+    let parser = Parser::new(config.clone(), original.to_string())
+        .with_path("test_helpers/guide/out/index.md");
+    assert!(parser
+        .parse()
+        .unwrap()
+        .contains("[example](../../code_snippet.rs)"));
+
+    let parser = Parser::new(config.clone(), original.to_string());
+    assert!(parser.parse().is_err());
+
+    // Front matter and footnotes are unchanged
+    let original = r#"<!--{ "guide/notes.md" | shift-headings: +1 | rewrite-links }-->
+<!--{ end }-->"#;
+    let expected = r#"<!--{ "guide/notes.md" | shift-headings: +1 | rewrite-links }-->
+---
+title: Notes
+---
+## Notes
+
+See the [setup](test_helpers/guide/setup.md).[^1]
+
+[^1]: Footnote text here
+<!--{ end }-->"#;
+    let parser = Parser::new(config, original.to_string()).with_path("README.md");
+    assert_eq!(parser.parse().unwrap(), expected);
+}
+
+#[test]
+fn include_file_containing_tag() {
+    let original = r#"# Guide
+<!--{ "tag_example.md" }-->
+<!--{ end }-->"#;
+    let expected = r#"# Guide
+<!--{ "tag_example.md" }-->
+## Syntax
+Write `<!--{ "file.rs" | code }-->` to include a file.
+<!--{ end }-->"#;
+    let config = ParserConfig {
+        base_dir: "test_helpers".into(),
+        ..Default::default()
+    };
+    let parsed = Parser::new(config.clone(), original.to_string())
+        .parse()
+        .unwrap();
+    assert_eq!(parsed, expected);
+
+    // Included text isn't parsed again when generating a table of contents
+    let original = format!("<!--{{ @toc }}-->\n<!--{{ end }}-->\n{}", original);
+    let expected = format!(
+        "<!--{{ @toc }}-->\n* [Guide](#guide)\n    * [Syntax](#syntax)\n<!--{{ end }}-->\n{}",
+        expected
+    );
+    let parsed = Parser::new(config, original).parse().unwrap();
+    assert_eq!(parsed, expected);
+}
+
+#[test]
+fn toc() {
+    let original = r#"# Title
+<!--{ @toc }-->
+<!--{ end }-->
+## Usage: `md-inc [options]`
+### Example
+## Included
+<!--{ "guide/setup.md" | shift-headings: +2 | lines: 1 7 }-->
+<!--{ end }-->
+```
+## Not a heading
+```
+## Example ##
+### Example
+#### Deepest"#;
+    let expected = r#"# Title
+<!--{ @toc }-->
+* [Title](#title)
+    * [Usage: `md-inc [options]`](#usage-md-inc-options)
+        * [Example](#example)
+    * [Included](#included)
+        * [Setup](#setup)
+            * [Configuration](#configuration)
+    * [Example](#example-1)
+        * [Example](#example-2)
+            * [Deepest](#deepest)
+<!--{ end }-->
+## Usage: `md-inc [options]`
+### Example
+## Included
+<!--{ "guide/setup.md" | shift-headings: +2 | lines: 1 7 }-->
+### Setup
+
+See the [install notes](install.md#linux) and the [example](../code_snippet.rs).
+
+![Diagram](images/diagram.png "Overview")
+
+#### Configuration
+<!--{ end }-->
+```
+## Not a heading
+```
+## Example ##
+### Example
+#### Deepest"#;
+    let config = ParserConfig {
+        base_dir: "test_helpers".into(),
+        ..Default::default()
+    };
+    let parsed = Parser::new(config.clone(), original.to_string())
+        .parse()
+        .unwrap();
+    assert_eq!(parsed, expected);
+    // Updating the table of contents again makes no changes
+    assert_eq!(
+        Parser::new(config.clone(), parsed.clone()).parse().unwrap(),
+        expected
+    );
+
+    let original = "# Title\n<!--{ @toc | max-depth: 2 }-->\n<!--{ end }-->\n## Usage\n### Example\n## Links [here](#usage)";
+    let parsed = Parser::new(config.clone(), original.to_string())
+        .parse()
+        .unwrap();
+    assert_eq!(
+        parsed,
+        "# Title\n<!--{ @toc | max-depth: 2 }-->\n* [Title](#title)\n    * [Usage](#usage)\n    * [Links here](#links-here)\n<!--{ end }-->\n## Usage\n### Example\n## Links [here](#usage)"
+    );
+    // Front matter and directives before a horizontal rule aren't headings
+    let original = r#"---
+title: My doc
+---
+# Title
+<!--{ @toc }-->
+<!--{ end }-->
+<!--{ "rule.md" }-->
+<!--{ end }-->"#;
+    let expected = r#"---
+title: My doc
+---
+# Title
+<!--{ @toc }-->
+* [Title](#title)
+    * [After the rule](#after-the-rule)
+<!--{ end }-->
+<!--{ "rule.md" }-->
+---
+## After the rule
+<!--{ end }-->"#;
+    let parsed = Parser::new(config, original.to_string()).parse().unwrap();
+    assert_eq!(parsed, expected);
+}
+
+#[test]
+fn cmd_max_depth() {
+    let original =
+        "* One\n    * Two\n      continued\n        * Three\n    * Two\n1. Numbered\n   - Nested";
+    let cmd = Command::new("max-depth", vec!["1"]);
+    assert_eq!(transform(original, &cmd).unwrap(), "* One\n1. Numbered");
+    let cmd = Command::new("max-depth", vec!["2"]);
+    assert_eq!(
+        transform(original, &cmd).unwrap(),
+        "* One\n    * Two\n      continued\n    * Two\n1. Numbered\n   - Nested"
+    );
+}
+
+#[test]
+fn unknown_source() {
+    let original = "<!--{ @unknown }-->\n<!--{ end }-->";
+    assert!(Parser::new(ParserConfig::default(), original.to_string())
+        .parse()
+        .is_err());
+}
+
+#[test]
+fn diff_source() {
+    let original = r#"<!--{ @diff "diff/v1.rs" "diff/v2.rs" context=1 | code: diff }-->
+<!--{ end }-->"#;
+    let expected = r#"<!--{ @diff "diff/v1.rs" "diff/v2.rs" context=1 | code: diff }-->
+```diff
+--- diff/v1.rs
++++ diff/v2.rs
+@@ -1,10 +1,12 @@
+ use std::fs;
++use std::io;
+ 
+-fn read(path: &str) -> String {
+-    fs::read_to_string(path).unwrap()
++fn read(path: &str) -> io::Result<String> {
++    fs::read_to_string(path)
+ }
+ 
+-fn main() {
+-    let text = read("input.txt");
++fn main() -> io::Result<()> {
++    let text = read("input.txt")?;
+     println!("{}", text);
++    Ok(())
+ }
+```
+<!--{ end }-->"#;
+    let config = ParserConfig {
+        base_dir: "test_helpers".into(),
+        ..Default::default()
+    };
+    let parsed = Parser::new(config.clone(), original.to_string())
+        .parse()
+        .unwrap();
+    assert_eq!(parsed, expected);
+
+    // Only compare the `main` functions, keeping the line numbers of each file
+    let original = r#"<!--{ @diff "diff/v1.rs" "diff/v2.rs" "start=^fn main" "end=^\}" context=0 }-->
+<!--{ end }-->"#;
+    let expected = r#"<!--{ @diff "diff/v1.rs" "diff/v2.rs" "start=^fn main" "end=^\}" context=0 }-->
+--- diff/v1.rs
++++ diff/v2.rs
+@@ -7,2 +8,2 @@
+-fn main() {
+-    let text = read("input.txt");
++fn main() -> io::Result<()> {
++    let text = read("input.txt")?;
+@@ -9,0 +11 @@
++    Ok(())
+<!--{ end }-->"#;
+    let parsed = Parser::new(config.clone(), original.to_string())
+        .parse()
+        .unwrap();
+    assert_eq!(parsed, expected);
+
+    let original = r#"<!--{ @diff "diff/v1.rs" "diff/v1.rs" }-->
+<!--{ end }-->"#;
+    let parsed = Parser::new(config.clone(), original.to_string())
+        .parse()
+        .unwrap();
+    assert_eq!(
+        parsed,
+        "<!--{ @diff \"diff/v1.rs\" \"diff/v1.rs\" }-->\n\n<!--{ end }-->"
+    );
+
+    let original = r#"<!--{ @diff "diff/v1.rs" "diff/v2.rs" lines=3-5 context=0 }-->
+<!--{ end }-->"#;
+    let parsed = Parser::new(config.clone(), original.to_string())
+        .parse()
+        .unwrap();
+    assert!(parsed.contains("@@ -3,3 +3,3 @@\n-fn read(path: &str) -> String {"));
+
+    // A reversed range can't be shown as a hunk
+    let original = r#"<!--{ @diff "diff/v1.rs" "diff/v2.rs" lines=5-3 }-->
+<!--{ end }-->"#;
+    let err = Parser::new(config.clone(), original.to_string())
+        .parse()
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "The lines compared in 'diff/v1.rs' must be a single ascending range, e.g. lines=3-8"
+    );
+
+    let original = "<!--{ @diff \"diff/v1.rs\" }-->\n<!--{ end }-->";
+    assert!(Parser::new(config, original.to_string()).parse().is_err());
+}
+
+#[cfg(unix)]
+#[test]
+fn exec_source() {
+    let mut config = ParserConfig {
+        base_dir: "test_helpers".into(),
+        ..Default::default()
+    };
+    let run = |config: &ParserConfig, block: &str| {
+        Parser::new(
+            config.clone(),
+            format!("<!--{{ {} }}-->\n<!--{{ end }}-->", block),
+        )
+        .parse()
+    };
+    // Commands can't run unless enabled
+    assert!(run(&config, r#"@exec "echo hello""#).is_err());
+
+    config.exec.allow = true;
+    assert_eq!(
+        run(&config, r#"@exec "echo 'hello  world'" | code: text"#).unwrap(),
+        "<!--{ @exec \"echo 'hello  world'\" | code: text }-->\n```text\nhello  world\n```\n<!--{ end }-->"
+    );
+    // Runs in the base directory
+    assert_eq!(
+        run(&config, r#"@exec cat short_4.txt | lines: 3 3"#).unwrap(),
+        "<!--{ @exec cat short_4.txt | lines: 3 3 }-->\nfirst\n<!--{ end }-->"
+    );
+    let output = |config: &ParserConfig, block: &str| {
+        let parsed = run(config, block)?;
+        Ok::<_, anyhow::Error>(parsed.lines().nth(1).unwrap_or_default().to_string())
+    };
+    assert_eq!(
+        output(
+            &config,
+            r#"@exec sh -c "echo out; echo err >&2" output=stderr"#
+        )
+        .unwrap(),
+        "err"
+    );
+    // Both streams are included in the order they were written
+    assert_eq!(
+        run(
+            &config,
+            r#"@exec sh -c "echo err >&2; echo out" output=both"#
+        )
+        .unwrap(),
+        "<!--{ @exec sh -c \"echo err >&2; echo out\" output=both }-->\nerr\nout\n<!--{ end }-->"
+    );
+    assert!(output(&config, r#"@exec sh -c "exit 2""#).is_err());
+    assert!(output(&config, r#"@exec sh -c "exit 2" exit=2"#).is_ok());
+    assert!(output(&config, r#"@exec sh -c "exit 2" exit=any"#).is_ok());
+
+    // Only allowed environment variables are passed
+    std::env::set_var("MD_INC_TEST_SECRET", "secret");
+    assert_eq!(
+        output(&config, r#"@exec sh -c "echo $MD_INC_TEST_SECRET.""#).unwrap(),
+        "."
+    );
+
+    config.exec.timeout = std::time::Duration::from_millis(100);
+    assert!(output(&config, r#"@exec sleep 5"#).is_err());
+
+    // A background process keeping the output open is stopped by the timeout too
+    let start = std::time::Instant::now();
+    assert!(output(&config, r#"@exec sh -c "sleep 5 & echo hi""#).is_err());
+    assert!(start.elapsed() < std::time::Duration::from_secs(4));
+}
+
+#[test]
+fn exec_split_words() {
+    use crate::exec::split_words;
+    assert_eq!(
+        split_words(r#"cargo run  --example "my demo" 'a "b"'"#).unwrap(),
+        vec!["cargo", "run", "--example", "my demo", "a \"b\""]
+    );
+    assert_eq!(split_words(r#"echo """#).unwrap(), vec!["echo", ""]);
+    assert!(split_words(r#"echo "open"#).is_err());
+}
+
+#[cfg(unix)]
+#[test]
+fn session_source() {
+    let config = ParserConfig {
+        base_dir: "test_helpers".into(),
+        exec: ExecConfig {
+            allow: true,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let original = r#"<!--{ @session "echo one" "sh -c 'echo two >&2; pwd'" "true" redact-paths=true }-->
+<!--{ end }-->"#;
+    let expected = r#"<!--{ @session "echo one" "sh -c 'echo two >&2; pwd'" "true" redact-paths=true }-->
+```console
+$ echo one
+one
+$ sh -c 'echo two >&2; pwd'
+two
+.
+$ true
+```
+<!--{ end }-->"#;
+    let parsed = Parser::new(config.clone(), original.to_string())
+        .parse()
+        .unwrap();
+    assert_eq!(parsed, expected);
+
+    let original = r#"<!--{ @session "echo 'took 1.25s'" "sh -c 'exit 1'" "redact=[0-9.]+s" exit=any "prompt=> " }-->
+<!--{ end }-->"#;
+    let parsed = Parser::new(config.clone(), original.to_string())
+        .parse()
+        .unwrap();
+    assert!(parsed.contains("```console\n> echo 'took 1.25s'\ntook ...\n> sh -c 'exit 1'\n```"));
+
+    let original = "<!--{ @session \"sh -c 'exit 1'\" }-->\n<!--{ end }-->";
+    assert!(Parser::new(config, original.to_string()).parse().is_err());
+}
+
+#[cfg(unix)]
+#[test]
+fn exec_cache() {
+    let dir = std::env::temp_dir().join(format!("md-inc-cache-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("input.txt"), "v1").unwrap();
+    let mut config = ParserConfig {
+        base_dir: dir.clone(),
+        exec: ExecConfig {
+            allow: true,
+            ..Default::default()
+        },
+        cache_dir: Some(dir.join("cache")),
+        ..Default::default()
+    };
+    // Counts the runs of the command in runs.log, which isn't an input file
+    let original = r#"<!--{ @exec "sh -c 'cat input.txt; echo; echo run >> runs.log; wc -l < runs.log'" "inputs=*.txt" }-->
+<!--{ end }-->"#;
+    let run = |config: &ParserConfig| {
+        Parser::new(config.clone(), original.to_string())
+            .parse()
+            .unwrap()
+    };
+    let first = run(&config);
+    assert!(first.contains("\nv1\n"));
+    // The output is read from the cache, so it doesn't change
+    assert_eq!(run(&config), first);
+
+    // Changing an input file runs the command again
+    std::fs::write(dir.join("input.txt"), "v2").unwrap();
+    let second = run(&config);
+    assert!(second.contains("\nv2\n"));
+    assert_eq!(run(&config), second);
+
+    // Changing the settings for running commands runs the command again
+    config.exec.timeout = std::time::Duration::from_secs(30);
+    let third = run(&config);
+    assert_ne!(third, second);
+    assert_eq!(run(&config), third);
+
+    // Refreshing runs the command again and caches the new output
+    config.refresh_cache = true;
+    let fourth = run(&config);
+    assert_ne!(fourth, third);
+    config.refresh_cache = false;
+    assert_eq!(run(&config), fourth);
+
+    // Without a cache directory, the command always runs
+    config.cache_dir = None;
+    assert_ne!(run(&config), fourth);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+///
+/// Creates a git repository in a temporary directory.
+/// Returns the directory and a function that runs git in it with a fixed author and date.
+///
+fn git_repo(name: &str) -> (PathBuf, impl Fn(&[&str]) -> String) {
+    let dir = std::env::temp_dir().join(format!("md-inc-{}-{}", name, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let repo = dir.clone();
+    let git = move |args: &[&str]| {
+        let output = std::process::Command::new("git")
+            .args([
+                "-c",
+                "user.name=md-inc",
+                "-c",
+                "user.email=md-inc@example.com",
+                "-c",
+                "commit.gpgsign=false",
+                "-c",
+                "tag.gpgsign=false",
+            ])
+            .args(args)
+            .current_dir(&repo)
+            .env("GIT_COMMITTER_DATE", "2020-06-01T12:30:00Z")
+            .env("GIT_AUTHOR_DATE", "2020-06-01T12:30:00Z")
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{:?}", output);
+        String::from_utf8(output.stdout).unwrap()
+    };
+    git(&["init", "-q"]);
+    (dir, git)
+}
+
+#[test]
+fn git_source() {
+    let (dir, git) = git_repo("git-source");
+    std::fs::write(
+        dir.join("main.rs"),
+        "fn main() {\n    println!(\"v1\");\n}\n",
+    )
+    .unwrap();
+    git(&["add", "main.rs"]);
+    git(&["commit", "-q", "-m", "First"]);
+    // The working tree is changed after the commit
+    std::fs::write(
+        dir.join("main.rs"),
+        "fn main() {\n    println!(\"v2\");\n}\n",
+    )
+    .unwrap();
+
+    let config = ParserConfig {
+        base_dir: dir.clone(),
+        ..Default::default()
+    };
+    let original = r#"<!--{ "git:HEAD:main.rs" | lines: 2 2 | line-numbers | code }-->
+<!--{ end }-->"#;
+    let expected = r#"<!--{ "git:HEAD:main.rs" | lines: 2 2 | line-numbers | code }-->
+```rust
+2:     println!("v1");
+```
+<!--{ end }-->"#;
+    let parsed = Parser::new(config.clone(), original.to_string())
+        .parse()
+        .unwrap();
+    assert_eq!(parsed, expected);
+
+    // Revisions can be compared with the working tree
+    let original = r#"<!--{ @diff "git:HEAD:main.rs" "main.rs" }-->
+<!--{ end }-->"#;
+    let parsed = Parser::new(config.clone(), original.to_string())
+        .parse()
+        .unwrap();
+    assert!(parsed.contains("-    println!(\"v1\");\n+    println!(\"v2\");"));
+
+    for source in &[
+        "git:HEAD:missing.rs",
+        "git:no-such-revision:main.rs",
+        "git:--output=x:main.rs",
+        "git:HEAD",
+    ] {
+        let original = format!("<!--{{ \"{}\" }}-->\n<!--{{ end }}-->", source);
+        assert!(Parser::new(config.clone(), original).parse().is_err());
+    }
+
+    // A directory outside a repository gives a clear error
+    let outside = std::env::temp_dir().join(format!("md-inc-no-git-{}", std::process::id()));
+    std::fs::create_dir_all(&outside).unwrap();
+    let config = ParserConfig {
+        base_dir: outside.clone(),
+        ..Default::default()
+    };
+    let original = "<!--{ \"git:HEAD:main.rs\" }-->\n<!--{ end }-->";
+    let err = Parser::new(config, original.to_string())
+        .parse()
+        .unwrap_err();
+    assert!(format!("{:#}", err).contains("is not in a git repository"));
+
+    std::fs::remove_dir_all(&outside).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn cmd_date_format() {
+    let cmd = Command::new("date-format", vec!["%Y-%m-%d"]);
+    assert_eq!(
+        transform("2020-06-01T12:30:00Z", &cmd).unwrap(),
+        "2020-06-01"
+    );
+    let cmd = Command::new("date-format", vec!["%d %B %Y %H:%M"]);
+    assert_eq!(transform("0", &cmd).unwrap(), "01 January 1970 00:00");
+    // Dates are shown in UTC
+    assert_eq!(
+        transform("2020-06-01T10:30:00+10:00", &cmd).unwrap(),
+        "01 June 2020 00:30"
+    );
+    assert!(transform("yesterday", &cmd).is_err());
+    let cmd = Command::new("date-format", vec!["%Y-%"]);
+    assert!(transform("0", &cmd).is_err());
+}
+
+#[test]
+fn git_metadata_sources() {
+    // Create a repository with a known commit date and tag
+    let (dir, git) = git_repo("git");
+    std::fs::write(dir.join("file.txt"), "text").unwrap();
+    git(&["add", "file.txt"]);
+    git(&["commit", "-q", "-m", "First"]);
+    git(&["tag", "v1.2.3"]);
+    let commit = git(&["rev-parse", "HEAD"]);
+
+    let config = ParserConfig {
+        base_dir: dir.clone(),
+        ..Default::default()
+    };
+    let source = |block: &str| {
+        let original = format!("<!--{{ {} }}-->\n<!--{{ end }}-->", block);
+        let parsed = Parser::new(config.clone(), original).parse()?;
+        Ok::<_, anyhow::Error>(parsed.lines().nth(1).unwrap_or_default().to_string())
+    };
+    assert_eq!(source("@git-commit").unwrap(), commit.trim());
+    assert!(commit.starts_with(&source("@git-commit short=true").unwrap()));
+    assert_eq!(source("@git-tag").unwrap(), "v1.2.3");
+    assert_eq!(
+        source("@git-date file.txt").unwrap(),
+        "2020-06-01T12:30:00Z"
+    );
+    assert_eq!(
+        source(r#"@git-date file.txt | date-format: "%B %Y""#).unwrap(),
+        "June 2020"
+    );
+    assert!(source("@git-date missing.txt").is_err());
+
+    std::env::set_var("SOURCE_DATE_EPOCH", "1591014600");
+    assert_eq!(source("@now").unwrap(), "2020-06-01T12:30:00Z");
+    std::env::remove_var("SOURCE_DATE_EPOCH");
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn glob_source() {
+    let config = ParserConfig {
+        base_dir: "test_helpers".into(),
+        ..Default::default()
+    };
+    let original = r#"<!--{ "glob/*.rs" | code }-->
+<!--{ end }-->"#;
+    let expected = r#"<!--{ "glob/*.rs" | code }-->
+```rust
+fn a() {}
+```
+
+```rust
+fn b() {}
+```
+<!--{ end }-->"#;
+    let parsed = Parser::new(config.clone(), original.to_string())
+        .parse()
+        .unwrap();
+    assert_eq!(parsed, expected);
+
+    // Each file is inserted into the template, and line numbers are kept
+    let original = r#"<!--{ "glob/**/*.rs" "template=#### {stem} ({path})\n{content}" "separator=\n---\n" | line-numbers }-->
+<!--{ end }-->"#;
+    let parsed = Parser::new(config.clone(), original.to_string())
+        .parse()
+        .unwrap();
+    assert_eq!(
+        parsed.lines().skip(1).collect::<Vec<_>>(),
+        vec![
+            "#### a (glob/a.rs)",
+            "2: fn a() {}",
+            "---",
+            "#### b (glob/b.rs)",
+            "1: fn b() {}",
+            "---",
+            "#### d (glob/nested/d.rs)",
+            "1: fn nested() {}",
+            "<!--{ end }-->",
+        ]
+    );
+
+    for source in &[r#""glob/*.md""#, r#""glob/*.rs" "template={name}""#] {
+        let original = format!("<!--{{ {} }}-->\n<!--{{ end }}-->", source);
+        assert!(Parser::new(config.clone(), original).parse().is_err());
+    }
+    // Glob syntax in the base directory is matched literally,
+    // and files with glob syntax in their name are included as files
+    let dir = std::env::temp_dir().join(format!("md-inc-glob[1]-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("a.rs"), "fn a() {}").unwrap();
+    std::fs::write(dir.join("notes[1].md"), "Notes").unwrap();
+    std::fs::write(dir.join("notes1.md"), "Wrong notes").unwrap();
+    let config = ParserConfig {
+        base_dir: dir.clone(),
+        ..Default::default()
+    };
+    let original =
+        "<!--{ \"*.rs\" }-->\n<!--{ end }-->\n<!--{ \"notes[1].md\" }-->\n<!--{ end }-->";
+    assert_eq!(
+        Parser::new(config, original.to_string()).parse().unwrap(),
+        "<!--{ \"*.rs\" }-->\nfn a() {}\n<!--{ end }-->\n<!--{ \"notes[1].md\" }-->\nNotes\n<!--{ end }-->"
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
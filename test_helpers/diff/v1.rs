@@ -0,0 +1,10 @@
+use std::fs;
+
+fn read(path: &str) -> String {
+    fs::read_to_string(path).unwrap()
+}
+
+fn main() {
+    let text = read("input.txt");
+    println!("{}", text);
+}
@@ -0,0 +1,12 @@
+use std::fs;
+use std::io;
+
+fn read(path: &str) -> io::Result<String> {
+    fs::read_to_string(path)
+}
+
+fn main() -> io::Result<()> {
+    let text = read("input.txt")?;
+    println!("{}", text);
+    Ok(())
+}